@@ -1,24 +1,15 @@
 extern crate test;
 use test::Bencher;
 
-use sdl2::render::WindowCanvas;
-
 use crate::flatshapes::*;
+use crate::pipeline::*;
+use crate::target::*;
+use crate::texture::*;
 use crate::*;
 
-fn get_test_canvas() -> WindowCanvas {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("graphics benchmark", 800, 600)
-        .position_centered()
-        .hidden()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::WHITE);
-    canvas.clear();
-    canvas.present();
+fn get_test_canvas() -> Framebuffer {
+    let mut canvas = Framebuffer::new(800, 600);
+    canvas.clear(Color::WHITE);
     canvas
 }
 
@@ -78,6 +69,74 @@ fn filled_tri(b: &mut Bencher) {
     });
 }
 
+// The scanline fill draw_filled_triangle used before the edge function rasterizer, kept as a
+// baseline for edge_tri
+fn scanline_fill(canvas: &mut impl RenderTarget, p0: Point, p1: Point, p2: Point, c: Color) {
+    // Sort points so that p0.y < p1.y < p2.y
+    let (p0, p1) = match p1.y < p0.y {
+        true => (p1, p0),
+        false => (p0, p1),
+    };
+    let (p0, p2) = match p2.y < p0.y {
+        true => (p2, p0),
+        false => (p0, p2),
+    };
+    let (p1, p2) = match p2.y < p1.y {
+        true => (p2, p1),
+        false => (p1, p2),
+    };
+
+    // x coords of lines 01, 12 and 02, the short sides 01 + 12 share a point
+    let mut x01_12 = crate::lerp(p0.y, p0.x as FP, p1.y, p1.x as FP);
+    x01_12.pop();
+    x01_12.append(&mut crate::lerp(p1.y, p1.x as FP, p2.y, p2.x as FP));
+    let x02 = crate::lerp(p0.y, p0.x as FP, p2.y, p2.x as FP);
+
+    let m = x02.len() / 2;
+    let (x_left, x_right) = match x02[m] < x01_12[m] {
+        true => (x02, x01_12),
+        false => (x01_12, x02),
+    };
+    for y in p0.y..p2.y {
+        let idx = (y - p0.y) as usize;
+        for x in x_left[idx] as i32..x_right[idx] as i32 {
+            put_color(canvas, Point::new(x, y), c);
+        }
+    }
+}
+
+// Same triangle as filled_tri without the outline
+#[bench]
+fn scanline_tri(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    b.iter(|| {
+        scanline_fill(
+            &mut canvas,
+            Point::new(100, 100),
+            Point::new(200, 120),
+            Point::new(120, 200),
+            Color::GREEN,
+        )
+    });
+}
+
+// Same triangle as scanline_tri, at sub-pixel positions
+#[bench]
+fn edge_tri(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let fill = |_: &Fragment<()>| Some(Color::GREEN);
+    b.iter(|| {
+        draw_varying_triangle(
+            &mut canvas,
+            None,
+            Xyzv::new(100.3, 100.6, 1.0, ()),
+            Xyzv::new(200.1, 120.4, 1.0, ()),
+            Xyzv::new(120.8, 200.2, 1.0, ()),
+            &fill,
+        )
+    });
+}
+
 #[bench]
 fn shaded_tri(b: &mut Bencher) {
     let mut canvas = get_test_canvas();
@@ -92,6 +151,49 @@ fn shaded_tri(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn depth_tri(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let mut depth = DepthBuffer::new(800, 600);
+    b.iter(|| {
+        depth.clear();
+        draw_depth_triangle(
+            &mut canvas,
+            &mut depth,
+            Xyzh::new(100.0, 100.0, 1.0, 1.0),
+            Xyzh::new(200.0, 120.0, 0.5, 0.7),
+            Xyzh::new(120.0, 200.0, 0.1, 0.0),
+            Color::GREEN,
+        )
+    });
+}
+
+#[bench]
+fn textured_tri(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let mut depth = DepthBuffer::new(800, 600);
+    let texels = (0..64 * 64)
+        .map(|idx| match (idx / 64 / 8 + idx % 64 / 8) % 2 {
+            0 => Color::BLACK,
+            _ => Color::WHITE,
+        })
+        .collect();
+    let texture = Texture::new(64, 64, texels);
+    b.iter(|| {
+        depth.clear();
+        draw_textured_triangle(
+            &mut canvas,
+            &mut depth,
+            Xyzuv::new(100.0, 100.0, 1.0, Vec2::new(0.0, 0.0), 1.0),
+            Xyzuv::new(200.0, 120.0, 0.5, Vec2::new(1.0, 0.0), 0.7),
+            Xyzuv::new(120.0, 200.0, 0.1, Vec2::new(0.0, 1.0), 0.0),
+            &texture,
+            Filter::Bilinear,
+            Color::WHITE,
+        )
+    });
+}
+
 #[bench]
 fn multishade_tri(b: &mut Bencher) {
     let mut canvas = get_test_canvas();
@@ -115,15 +217,62 @@ fn project(b: &mut Bencher) {
     b.iter(|| project_vertex(&camera, &v));
 }
 
+// Throughput of the whole teapot going from object space to clip space, one vertex at a time
+// and in SIMD lanes
+#[bench]
+fn project_each(b: &mut Bencher) {
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (800, 600));
+    let vertices = crate::meshes::teapot().vertices;
+    let model = affine(
+        Vec3::splat(4.0),
+        Quat::from_rotation_y(0.5),
+        Vec3::new(0.0, 3.0, 0.0),
+    );
+    b.bytes = std::mem::size_of_val(&vertices[..]) as u64;
+    b.iter(|| {
+        let view_projection = camera.view_projection();
+        vertices
+            .iter()
+            .map(|v| view_projection * transform_point(&model, *v).extend(1.0))
+            .collect::<Vec<_>>()
+    });
+}
+
+#[bench]
+fn project_batch(b: &mut Bencher) {
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (800, 600));
+    let vertices = crate::meshes::teapot().vertices;
+    let model = affine(
+        Vec3::splat(4.0),
+        Quat::from_rotation_y(0.5),
+        Vec3::new(0.0, 3.0, 0.0),
+    );
+    b.bytes = std::mem::size_of_val(&vertices[..]) as u64;
+    b.iter(|| {
+        crate::soa::SoaPoints::new(&vertices)
+            .transform(&Mat4::from(model))
+            .to_clip(&camera)
+    });
+}
+
+#[bench]
+fn clip_tri(b: &mut Bencher) {
+    // Crosses the near plane and the left side of the frustum
+    let tri = [
+        Vec4::new(-3.0, 0.0, 1.0, 2.0),
+        Vec4::new(1.0, 0.5, -0.5, 0.5),
+        Vec4::new(0.5, -1.0, 2.0, 3.0),
+    ];
+    b.iter(|| crate::clip::clip_polygon(&tri, crate::clip::ClipPlane::FRUSTUM));
+}
+
 #[bench]
 fn cube(b: &mut Bencher) {
     let mut canvas = get_test_canvas();
     let camera = Camera::new(None, None, None, (800, 600));
     let cube = Object {
-        pos: Vec3::ZERO,
-        rot: Quat::default(),
-        scale: 4.0,
-        mesh: Rc::new(crate::meshes::cube()),
+        transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+        ..Object::new(Rc::new(crate::meshes::cube()))
     };
     b.iter(|| cube.render(&mut canvas, &camera));
 }
@@ -133,10 +282,56 @@ fn teapot(b: &mut Bencher) {
     let mut canvas = get_test_canvas();
     let camera = Camera::new(None, None, None, (800, 600));
     let teapot = Object {
-        pos: Vec3::ZERO,
-        rot: Quat::default(),
-        scale: 4.0,
-        mesh: Rc::new(crate::meshes::teapot()),
+        transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+        ..Object::new(Rc::new(crate::meshes::teapot()))
     };
     b.iter(|| teapot.render(&mut canvas, &camera));
 }
+
+#[bench]
+fn teapot_culled(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (800, 600));
+    let teapot = Object {
+        transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+        cull: CullMode::Back,
+        ..Object::new(Rc::new(crate::meshes::teapot()))
+    };
+    b.iter(|| teapot.render(&mut canvas, &camera));
+}
+
+#[bench]
+fn teapot_solid(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let mut depth = DepthBuffer::new(800, 600);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (800, 600));
+    let teapot = Object {
+        transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+        ..Object::new(Rc::new(crate::meshes::teapot()))
+    };
+    b.iter(|| {
+        depth.clear();
+        teapot.render_solid(&mut canvas, &mut depth, &camera, &[])
+    });
+}
+
+#[bench]
+fn teapot_tiled(b: &mut Bencher) {
+    let mut canvas = get_test_canvas();
+    let mut depth = DepthBuffer::new(800, 600);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (800, 600));
+    let teapot = Object {
+        transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+        ..Object::new(Rc::new(crate::meshes::teapot()))
+    };
+    let scene = Scene {
+        camera,
+        objects: vec![teapot],
+        lights: Vec::new(),
+        threads: 0,
+    };
+    b.iter(|| {
+        depth.clear();
+        scene.render_tiled(&mut canvas, &mut depth)
+    });
+}
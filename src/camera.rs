@@ -2,157 +2,187 @@ use std::cmp::Ordering;
 
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
 
 use crate::math::*;
 
+// How camera space (x right, y down, z forward) maps to clip space.
+// Clip space keeps the visible area within -w..=w for x and y and 0..=w for z, which goes from
+// the near to the far plane. The far plane can be infinitely far away for perspective.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // Field of view in radians across the longer side of the screen
+    Perspective {
+        fov: FP,
+        near: FP,
+        far: FP,
+    },
+    // Extent in camera space units across the longer side of the screen
+    Orthographic {
+        size: FP,
+        near: FP,
+        far: FP,
+    },
+    // Perspective with the corners of the visible area on the near plane given directly,
+    // which can be off center. Stretched to fit the screen, whatever its aspect ratio.
+    Frustum {
+        min: Vec2,
+        max: Vec2,
+        near: FP,
+        far: FP,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self, res: (u32, u32)) -> Mat4 {
+        // Half the extent of the visible area on the near plane, or at any depth for orthographic
+        let half_size = |longer: FP| {
+            let ratio = res.0 as FP / res.1 as FP;
+            match ratio.partial_cmp(&1.0) {
+                Some(Ordering::Less) => Vec2::new(longer * ratio, longer),
+                _ => Vec2::new(longer, longer / ratio),
+            }
+        };
+        match *self {
+            Projection::Perspective { fov, near, far } => {
+                let half = half_size(near * (fov / 2.0).tan());
+                Projection::Frustum {
+                    min: -half,
+                    max: half,
+                    near,
+                    far,
+                }
+                .matrix(res)
+            }
+            Projection::Orthographic { size, near, far } => {
+                let scale = half_size(size / 2.0).recip();
+                Mat4::from_cols(
+                    Vec4::new(scale.x, 0.0, 0.0, 0.0),
+                    Vec4::new(0.0, scale.y, 0.0, 0.0),
+                    Vec4::new(0.0, 0.0, 1.0 / (far - near), 0.0),
+                    Vec4::new(0.0, 0.0, -near / (far - near), 1.0),
+                )
+            }
+            Projection::Frustum {
+                min,
+                max,
+                near,
+                far,
+            } => {
+                let scale = 2.0 * near / (max - min);
+                let center = (max + min) / (max - min);
+                let depth = match far.is_finite() {
+                    true => far / (far - near),
+                    false => 1.0,
+                };
+                Mat4::from_cols(
+                    Vec4::new(scale.x, 0.0, 0.0, 0.0),
+                    Vec4::new(0.0, scale.y, 0.0, 0.0),
+                    Vec4::new(-center.x, -center.y, depth, 1.0),
+                    Vec4::new(0.0, 0.0, -near * depth, 0.0),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Camera {
     pub pos: Vec3,
     pub rot: Quat,
     res: (u32, u32),
-    pub fov: u8,
-    pub viewport: Vec4,
+    pub projection: Projection,
 }
 
 impl Camera {
+    // Perspective camera with nothing past the near plane clipped away, fov is in degrees
     pub fn new(pos: Option<Vec3>, rot: Option<Quat>, fov: Option<u8>, res: (u32, u32)) -> Camera {
-        let pos = pos.unwrap_or_default();
-        let rot = rot.unwrap_or_default();
-        let fov = fov.unwrap_or(90);
-        let viewport = viewport(fov, res, None);
+        let fov = fov.unwrap_or(90) as FP;
+        Camera {
+            pos: pos.unwrap_or_default(),
+            rot: rot.unwrap_or_default(),
+            res,
+            projection: Projection::Perspective {
+                fov: fov.to_radians(),
+                near: 1.0,
+                far: FP::INFINITY,
+            },
+        }
+    }
+    // Orthographic camera showing `size` units across the longer side, from its position up to
+    // `far` units ahead
+    pub fn orthographic(pos: Vec3, rot: Quat, size: FP, far: FP, res: (u32, u32)) -> Camera {
         Camera {
             pos,
             rot,
             res,
-            fov,
-            viewport,
+            projection: Projection::Orthographic {
+                size,
+                near: 0.0,
+                far,
+            },
         }
     }
-    pub fn local_move(&mut self, offset: Vec3) {
-        self.pos += self.rot.conjugate() * offset;
+    pub fn res(&self) -> (u32, u32) {
+        self.res
     }
-    pub fn look(&mut self, offset: Quat) {
-        self.rot *= offset;
-        self.rot = self.rot.normalize();
+    // World space to camera space
+    pub fn view(&self) -> Mat4 {
+        Mat4::from_quat(self.rot) * Mat4::from_translation(plain(-self.pos))
     }
+    pub fn projection_matrix(&self) -> Mat4 {
+        self.projection.matrix(self.res)
+    }
+    // World space to clip space
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection_matrix() * self.view()
+    }
+    // Only changes perspective projections, in degrees
     pub fn change_fov(&mut self, fov: u8) {
         if fov == 0 || fov >= 180 {
             return;
         }
-        self.viewport = viewport(fov, self.res, Some(self.viewport.z));
-        self.fov = fov;
+        if let Projection::Perspective { fov: current, .. } = &mut self.projection {
+            *current = (fov as FP).to_radians();
+        }
+    }
+    // Field of view of a perspective projection in whole degrees
+    pub fn fov(&self) -> Option<u8> {
+        match self.projection {
+            Projection::Perspective { fov, .. } => Some(fov.to_degrees().round() as u8),
+            _ => None,
+        }
+    }
+    // Only changes orthographic projections, a factor above 1 shows more of the scene
+    pub fn zoom(&mut self, factor: FP) {
+        if let Projection::Orthographic { size, .. } = &mut self.projection {
+            *size *= factor;
+        }
     }
     pub fn change_res(&mut self, res: (u32, u32)) {
-        self.viewport = viewport(self.fov, res, Some(self.viewport.z));
         self.res = res;
     }
-    pub fn process_inputs(&mut self, event_pump: &mut EventPump) {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    std::process::exit(0); // Would be better to handle in main()
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::W),
-                    ..
-                } => {
-                    self.local_move(Vec3::new(0.0, 0.0, 0.1));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::A),
-                    ..
-                } => {
-                    self.local_move(Vec3::new(-0.1, 0.0, 0.0));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::S),
-                    ..
-                } => {
-                    self.local_move(Vec3::new(0.0, 0.0, -0.1));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::D),
-                    ..
-                } => {
-                    self.local_move(Vec3::new(0.1, 0.0, 0.0));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => {
-                    self.look(Quat::from_rotation_x(-0.01));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => {
-                    self.look(Quat::from_rotation_x(0.01));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => {
-                    self.look(Quat::from_rotation_y(0.01));
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => {
-                    self.look(Quat::from_rotation_y(-0.01));
-                }
-                Event::MouseWheel { y, .. } => {
-                    self.change_fov((self.fov as i32 + y) as u8);
-                }
-                Event::Window {
-                    win_event: WindowEvent::SizeChanged(x, y),
-                    ..
-                } => {
-                    self.change_res((x as u32, y as u32));
-                }
-                // Mouse movement disabled because an mouse unlock is needed and the camera rolls
-                // Event::MouseMotion { xrel, yrel, .. } => self.look(
-                //     Quat::from_rotation_y(xrel as FP / 100.0)
-                //         * Quat::from_rotation_x(yrel as FP / 100.0),
-                // ),
-                _ => {}
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => {
+                std::process::exit(0); // Would be better to handle in main()
             }
+            // Moving and turning depend on which keys are held, see controls::Movement
+            Event::MouseWheel { y, .. } => match self.fov() {
+                Some(fov) => self.change_fov((fov as i32 + y) as u8),
+                None => self.zoom((0.9 as FP).powi(y)),
+            },
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(x, y),
+                ..
+            } => {
+                self.change_res((x as u32, y as u32));
+            }
+            // Mouse look is done by controls::FirstPerson, which keeps the horizon level
+            _ => {}
         }
     }
 }
-
-fn viewport(fov: u8, res: (u32, u32), d: Option<FP>) -> Vec4 {
-    // Create a viewport plane d away from the camera with a matching FOV and aspect ratio
-    // The longer side according to the ratio will have the full FOV, while the other will be scaled accordingly
-
-    let side_bc = d.unwrap_or(1.0);
-    let angle_c = (fov as FP) / 180.0 * crate::math::FRAC_PI_2;
-    let angle_a = crate::math::FRAC_PI_2 - angle_c;
-    let side_ab = side_bc * (angle_c.sin() / angle_a.sin());
-
-    // Resize shorter size to fit ratio
-    let ratio = res.0 as FP / res.1 as FP;
-    match ratio.partial_cmp(&(1.0)).unwrap() {
-        Ordering::Equal => Vec4::new(
-            side_ab * 2.0,
-            side_ab * 2.0,
-            side_bc,
-            res.0 as FP / (side_ab * 2.0),
-        ),
-        Ordering::Less => Vec4::new(
-            side_ab * 2.0 * ratio,
-            side_ab * 2.0,
-            side_bc,
-            res.1 as FP / (side_ab * 2.0),
-        ),
-        Ordering::Greater => Vec4::new(
-            side_ab * 2.0,
-            side_ab * 2.0 * ratio.recip(),
-            side_bc,
-            res.0 as FP / (side_ab * 2.0),
-        ),
-    }
-}
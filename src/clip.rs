@@ -0,0 +1,141 @@
+use sdl2::rect::Point;
+
+use crate::camera::*;
+use crate::math::*;
+
+// Clip space is what the camera's Projection produces, w is the camera space depth
+// for perspective projections and 1 for orthographic ones
+pub fn to_clip(camera: &Camera, point: &Vec3) -> Vec4 {
+    camera.view_projection() * point.extend(1.0)
+}
+
+// Perspective divide and scale to canvas, also returns the depth to test with, which is 1 at
+// the near plane and goes down to 0 at the far plane. For perspective it is linear in 1/z.
+// Only valid for points that have been clipped against the near plane.
+pub fn clip_to_screen(camera: &Camera, v: Vec4) -> (Point, FP) {
+    let (res, depth) = clip_to_viewport(camera, v);
+    (Point::new(res.x as i32, res.y as i32), depth)
+}
+
+// Same as clip_to_screen, but keeps the position within the pixel
+pub fn clip_to_viewport(camera: &Camera, v: Vec4) -> (Vec2, FP) {
+    let ndc = v.xy() / v.w;
+    let (width, height) = camera.res();
+    let res = (ndc + 1.0) * Vec2::new(width as FP, height as FP) / 2.0;
+    (res, (v.w - v.z) / v.w)
+}
+
+// Twice the signed area of a triangle in NDC scaled by the product of its w's,
+// positive when it appears clockwise on screen (y points down).
+// Can be used before clipping, the sign is still correct for vertices behind the camera.
+pub fn signed_area(tri: &[Vec4; 3]) -> FP {
+    Mat3::from_cols(tri[0].xyw(), tri[1].xyw(), tri[2].xyw()).determinant()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipPlane {
+    Near,
+    Far,
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl ClipPlane {
+    pub const NEAR: &'static [ClipPlane] = &[ClipPlane::Near];
+    pub const FRUSTUM: &'static [ClipPlane] = &[
+        ClipPlane::Near,
+        ClipPlane::Far,
+        ClipPlane::Left,
+        ClipPlane::Right,
+        ClipPlane::Top,
+        ClipPlane::Bottom,
+    ];
+
+    // Signed distance to the plane, positive is inside
+    pub fn distance(&self, v: Vec4) -> FP {
+        match self {
+            ClipPlane::Near => v.z,
+            ClipPlane::Far => v.w - v.z,
+            ClipPlane::Left => v.w + v.x,
+            ClipPlane::Right => v.w - v.x,
+            ClipPlane::Top => v.w + v.y,
+            ClipPlane::Bottom => v.w - v.y,
+        }
+    }
+}
+
+// Vertex that can be clipped, either a bare clip space position or one with attributes
+pub trait ClipVertex: Lerp {
+    fn position(&self) -> Vec4;
+}
+
+impl ClipVertex for Vec4 {
+    fn position(&self) -> Vec4 {
+        *self
+    }
+}
+
+impl<T: Lerp> ClipVertex for (Vec4, T) {
+    fn position(&self) -> Vec4 {
+        self.0
+    }
+}
+
+// Sutherland-Hodgman clipping of a convex polygon, returns an empty vec if it is fully outside.
+// Attributes are interpolated linearly in clip space, which keeps them perspective correct.
+pub fn clip_polygon<T: ClipVertex>(polygon: &[T], planes: &[ClipPlane]) -> Vec<T> {
+    match is_inside(polygon, planes) {
+        true => polygon.to_vec(),
+        false => clip_polygon_edges(polygon, planes)
+            .into_iter()
+            .map(|(v, _)| v)
+            .collect(),
+    }
+}
+
+// Same as clip_polygon, also telling for each vertex whether the edge from it to the next one
+// is part of an edge of the input polygon. The others run along one of the planes.
+pub fn clip_polygon_edges<T: ClipVertex>(polygon: &[T], planes: &[ClipPlane]) -> Vec<(T, bool)> {
+    let mut output: Vec<(T, bool)> = polygon.iter().map(|&v| (v, true)).collect();
+    // Fast path for polygons that are entirely inside
+    if is_inside(polygon, planes) {
+        return output;
+    }
+    for plane in planes {
+        if output.is_empty() {
+            break;
+        }
+        let input = std::mem::take(&mut output);
+        for (idx, &(cur, original)) in input.iter().enumerate() {
+            let next = input[(idx + 1) % input.len()].0;
+            let d_cur = plane.distance(cur.position());
+            let d_next = plane.distance(next.position());
+            if d_cur >= 0.0 {
+                output.push((cur, original));
+            }
+            // Edge crosses the plane, add the intersection. Leaving the inside, the edge from
+            // the intersection to the next vertex kept is along the plane.
+            if (d_cur >= 0.0) != (d_next >= 0.0) {
+                let intersection = cur.lerp(next, d_cur / (d_cur - d_next));
+                output.push((intersection, original && d_cur < 0.0));
+            }
+        }
+    }
+    output
+}
+
+fn is_inside<T: ClipVertex>(polygon: &[T], planes: &[ClipPlane]) -> bool {
+    polygon.iter().all(|v| {
+        planes
+            .iter()
+            .all(|plane| plane.distance(v.position()) >= 0.0)
+    })
+}
+
+// Split a convex polygon into a fan of triangles
+pub fn triangulate_fan<T: Copy>(polygon: &[T]) -> impl Iterator<Item = [T; 3]> + '_ {
+    (1..polygon.len().saturating_sub(1))
+        .map(move |idx| [polygon[0], polygon[idx], polygon[idx + 1]])
+}
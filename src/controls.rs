@@ -0,0 +1,375 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{KeyboardState, Keycode, Scancode};
+use sdl2::mouse::MouseUtil;
+use sdl2::EventPump;
+
+use crate::camera::{Camera, Projection};
+use crate::math::*;
+use crate::scene::Scene;
+
+// Turn to the left about world y and tilt downwards, in radians, of a camera looking towards
+// `forward`, which has to be normalized
+fn yaw_pitch(forward: Vec3) -> (FP, FP) {
+    (
+        (-forward.x).atan2(forward.z),
+        forward.y.clamp(-1.0, 1.0).asin(),
+    )
+}
+
+// World space to camera space rotation with the horizon level
+fn yaw_pitch_rotation(yaw: FP, pitch: FP) -> Quat {
+    Quat::from_rotation_x(pitch) * Quat::from_rotation_y(yaw)
+}
+
+// Which way the keys held down at the moment steer, so that movement doesn't depend on how often
+// keys repeat
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeldKeys {
+    // Camera space directions, x right, y down and z forward, each -1, 0 or 1
+    pub movement: Vec3,
+    // Turning right and down, each -1, 0 or 1
+    pub turn: Vec2,
+    pub sprint: bool,
+}
+
+impl HeldKeys {
+    // WASD moves, Space goes up and Ctrl down, Shift sprints and the arrow keys turn
+    pub fn from_keyboard(keyboard: &KeyboardState) -> HeldKeys {
+        let held = |key| keyboard.is_scancode_pressed(key);
+        let axis = |less, more| held(more) as u8 as FP - held(less) as u8 as FP;
+        HeldKeys {
+            movement: Vec3::new(
+                axis(Scancode::A, Scancode::D),
+                axis(Scancode::Space, Scancode::LCtrl),
+                axis(Scancode::S, Scancode::W),
+            ),
+            turn: Vec2::new(
+                axis(Scancode::Left, Scancode::Right),
+                axis(Scancode::Up, Scancode::Down),
+            ),
+            sprint: held(Scancode::LShift) || held(Scancode::RShift),
+        }
+    }
+}
+
+// Turns held keys into how far to move and turn over some time, so that the camera moves just
+// as fast at any frame rate. Moving speeds up and slows down gradually, turning doesn't.
+pub struct Movement {
+    // Units per second
+    pub speed: FP,
+    // How many times faster sprinting is
+    pub sprint: FP,
+    // Units per second squared for both speeding up and slowing down, infinite for none
+    pub acceleration: FP,
+    // Radians per second
+    pub turn_speed: FP,
+    velocity: Vec3,
+}
+
+impl Movement {
+    pub fn new() -> Movement {
+        Movement {
+            speed: 3.0,
+            sprint: 3.0,
+            acceleration: 30.0,
+            turn_speed: 1.5,
+            velocity: Vec3::ZERO,
+        }
+    }
+    // In the same directions as HeldKeys::movement
+    pub fn velocity(&self) -> Vec3 {
+        self.velocity
+    }
+    // Offset to move by after `dt` seconds, in the same directions as HeldKeys::movement
+    pub fn step(&mut self, keys: &HeldKeys, dt: FP) -> Vec3 {
+        let top_speed = match keys.sprint {
+            true => self.speed * self.sprint,
+            false => self.speed,
+        };
+        // Diagonals aren't any faster
+        let target = keys.movement.normalize_or_zero() * top_speed;
+        let start = self.velocity;
+        let change = target - start;
+        // The speed changes steadily until reaching the target and then stays there, which can be
+        // partway through long frames
+        let ramp = (change.length() / self.acceleration).min(dt);
+        self.velocity = match ramp < dt {
+            true => target,
+            false => start + change.clamp_length_max(self.acceleration * dt),
+        };
+        (start + self.velocity) / 2.0 * ramp + self.velocity * (dt - ramp)
+    }
+    // Radians to turn right and down after `dt` seconds
+    pub fn turn(&self, keys: &HeldKeys, dt: FP) -> Vec2 {
+        keys.turn * self.turn_speed * dt
+    }
+}
+
+impl Default for Movement {
+    fn default() -> Movement {
+        Movement::new()
+    }
+}
+
+// Looks around by turning about the world's vertical axis and then tilting up or down, so the
+// horizon always stays level. Moving the mouse looks around while it is grabbed, which Tab
+// toggles. Held keys move along the ground and straight up and down, whichever way the camera
+// looks. Everything else is left to the camera.
+pub struct FirstPerson {
+    // Turn to the left about world y, in radians
+    pub yaw: FP,
+    // Tilt downwards, in radians within -MAX_PITCH..=MAX_PITCH
+    pub pitch: FP,
+    // Radians turned per pixel of mouse movement
+    pub sensitivity: FP,
+    pub movement: Movement,
+    grabbed: bool,
+}
+
+impl FirstPerson {
+    // Looking straight up or down, any further and the view would flip over
+    pub const MAX_PITCH: FP = FRAC_PI_2;
+
+    // Starts out looking the same way as `camera`, minus any roll
+    pub fn new(camera: &Camera) -> FirstPerson {
+        let (yaw, pitch) = yaw_pitch(camera.rot.conjugate() * Vec3::Z);
+        FirstPerson {
+            yaw,
+            pitch,
+            sensitivity: 0.003,
+            movement: Movement::new(),
+            grabbed: false,
+        }
+    }
+    pub fn grabbed(&self) -> bool {
+        self.grabbed
+    }
+    pub fn set_grabbed(&mut self, grabbed: bool) {
+        self.grabbed = grabbed;
+    }
+    // Turns right by `right` and down by `down` radians
+    pub fn look(&mut self, right: FP, down: FP) {
+        self.yaw = (self.yaw - right).rem_euclid(TAU);
+        self.pitch = (self.pitch + down).clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+    }
+    // World space to camera space rotation looking the current way
+    pub fn rotation(&self) -> Quat {
+        yaw_pitch_rotation(self.yaw, self.pitch)
+    }
+    // Moves and turns for the keys held over the last `dt` seconds
+    pub fn update(&mut self, camera: &mut Camera, keys: &HeldKeys, dt: FP) {
+        let turn = self.movement.turn(keys, dt);
+        self.look(turn.x, turn.y);
+        let offset = self.movement.step(keys, dt);
+        let along_ground = Quat::from_rotation_y(-self.yaw) * Vec3::new(offset.x, 0.0, offset.z);
+        camera.pos += along_ground + Vec3::new(0.0, offset.y, 0.0);
+        camera.rot = self.rotation();
+    }
+    // Syncs the mouse mode with whether it is grabbed, since only SDL can change that
+    pub fn process_inputs(
+        &mut self,
+        camera: &mut Camera,
+        event_pump: &mut EventPump,
+        mouse: &MouseUtil,
+        dt: FP,
+    ) {
+        for event in event_pump.poll_iter() {
+            self.handle_event(camera, &event);
+        }
+        let keys = HeldKeys::from_keyboard(&event_pump.keyboard_state());
+        self.update(camera, &keys, dt);
+        if mouse.relative_mouse_mode() != self.grabbed {
+            mouse.set_relative_mouse_mode(self.grabbed);
+        }
+    }
+    pub fn handle_event(&mut self, camera: &mut Camera, event: &Event) {
+        match *event {
+            Event::MouseMotion { xrel, yrel, .. } => {
+                if self.grabbed {
+                    self.look(xrel as FP * self.sensitivity, yrel as FP * self.sensitivity);
+                }
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::Tab),
+                repeat: false,
+                ..
+            } => self.grabbed = !self.grabbed,
+            // Let go of the mouse when switching to another window
+            Event::Window {
+                win_event: WindowEvent::FocusLost,
+                ..
+            } => self.grabbed = false,
+            _ => camera.handle_event(event),
+        }
+        camera.rot = self.rotation();
+    }
+}
+
+// Circles around a target, always looking at it, for inspecting models.
+// Dragging with the left mouse button rotates around the target, with the right or middle one it
+// pans, and the mouse wheel dollies in and out. Tab selects the next object and F frames it.
+pub struct Orbit {
+    pub target: Vec3,
+    // How far the camera is from the target
+    pub distance: FP,
+    // Same angles as for FirstPerson, of the direction from the camera to the target
+    pub yaw: FP,
+    pub pitch: FP,
+    // Radians turned per pixel dragged
+    pub sensitivity: FP,
+    // Index of the object F frames
+    pub selected: usize,
+}
+
+impl Orbit {
+    // Looks at `target` from where `camera` is
+    pub fn new(camera: &Camera, target: Vec3) -> Orbit {
+        let forward = (target - camera.pos)
+            .try_normalize()
+            .unwrap_or(camera.rot.conjugate() * Vec3::Z);
+        let (yaw, pitch) = yaw_pitch(forward);
+        Orbit {
+            target,
+            distance: (target - camera.pos).length(),
+            yaw,
+            pitch,
+            sensitivity: 0.01,
+            selected: 0,
+        }
+    }
+    pub fn rotation(&self) -> Quat {
+        yaw_pitch_rotation(self.yaw, self.pitch)
+    }
+    // Puts the camera on the orbit
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.rot = self.rotation();
+        camera.pos = self.target - camera.rot.conjugate() * Vec3::Z * self.distance;
+    }
+    // Moves the camera right by `right` and down by `down` radians around the target
+    pub fn rotate(&mut self, right: FP, down: FP) {
+        self.yaw = (self.yaw - right).rem_euclid(TAU);
+        self.pitch = (self.pitch + down).clamp(-FirstPerson::MAX_PITCH, FirstPerson::MAX_PITCH);
+    }
+    // Drags the target along by a number of pixels, so that it follows the mouse
+    pub fn pan(&mut self, camera: &Camera, right: FP, down: FP) {
+        let pixel = self.pixel_size(camera);
+        let rot = self.rotation().conjugate();
+        self.target -= (rot * Vec3::X * right + rot * Vec3::Y * down) * pixel;
+    }
+    // Scales the distance to the target, orthographic cameras show the same amount more or less.
+    // Stops just before the near plane would reach the target and clip it away.
+    pub fn dolly(&mut self, camera: &mut Camera, factor: FP) {
+        let near = match camera.projection {
+            Projection::Perspective { near, .. }
+            | Projection::Orthographic { near, .. }
+            | Projection::Frustum { near, .. } => near,
+        };
+        let distance = (self.distance * factor).max(near * 1.01).max(0.01);
+        camera.zoom(distance / self.distance);
+        self.distance = distance;
+    }
+    // Centers the view on a box and moves close enough for all of it to be just on screen
+    pub fn frame(&mut self, camera: &mut Camera, min: Vec3, max: Vec3) {
+        self.target = (min + max) / 2.0;
+        let radius = ((max - min).length() / 2.0).max(FP::EPSILON);
+        let (width, height) = camera.res();
+        let ratio = width.max(height) as FP / width.min(height).max(1) as FP;
+        self.distance = match &mut camera.projection {
+            Projection::Perspective { fov, near, .. } => {
+                // Across the shorter side the view is narrower than the field of view
+                let half = ((*fov / 2.0).tan() / ratio).atan();
+                let distance = radius / half.sin();
+                *near = near.min((distance - radius) / 2.0);
+                distance
+            }
+            Projection::Orthographic { size, near, far } => {
+                *size = 2.0 * radius * ratio;
+                *near = 0.0;
+                *far = 4.0 * radius;
+                2.0 * radius
+            }
+            Projection::Frustum { min, max, near, .. } => {
+                let half = ((*max - *min).min_element() / 2.0 / *near).atan();
+                radius / half.sin()
+            }
+        };
+        self.apply(camera);
+    }
+    // Width in world units of a pixel at the distance of the target
+    fn pixel_size(&self, camera: &Camera) -> FP {
+        let (width, height) = camera.res();
+        let longer = width.max(height).max(1) as FP;
+        match camera.projection {
+            Projection::Perspective { fov, .. } => 2.0 * self.distance * (fov / 2.0).tan() / longer,
+            Projection::Orthographic { size, .. } => size / longer,
+            // Stretched to the screen, so only about right
+            Projection::Frustum { min, max, near, .. } => {
+                (max - min).max_element() * self.distance / near / longer
+            }
+        }
+    }
+    pub fn process_inputs(&mut self, scene: &mut Scene, event_pump: &mut EventPump) {
+        for event in event_pump.poll_iter() {
+            self.handle_event(scene, &event);
+        }
+    }
+    // Anything not meant for the orbit goes to the camera, which is put back on the orbit after
+    pub fn handle_event(&mut self, scene: &mut Scene, event: &Event) {
+        let camera = &mut scene.camera;
+        match *event {
+            Event::MouseMotion {
+                mousestate,
+                xrel,
+                yrel,
+                ..
+            } => {
+                let (right, down) = (xrel as FP, yrel as FP);
+                if mousestate.left() {
+                    self.rotate(right * self.sensitivity, down * self.sensitivity);
+                } else if mousestate.right() || mousestate.middle() {
+                    self.pan(camera, right, down);
+                }
+            }
+            Event::MouseWheel { y, .. } => self.dolly(camera, (0.9 as FP).powi(y)),
+            Event::KeyDown {
+                keycode: Some(Keycode::Tab),
+                ..
+            } => self.selected = (self.selected + 1) % scene.objects.len().max(1),
+            Event::KeyDown {
+                keycode: Some(Keycode::F),
+                ..
+            } => {
+                if let Some((min, max)) = scene.objects.get(self.selected).and_then(|o| o.bounds())
+                {
+                    self.frame(camera, min, max);
+                }
+            }
+            _ => camera.handle_event(event),
+        }
+        self.apply(camera);
+    }
+}
+
+// One of the ways to steer the camera of a scene, picked at startup
+pub enum Controller {
+    FirstPerson(FirstPerson),
+    Orbit(Orbit),
+}
+
+impl Controller {
+    // `dt` is the time since the last call in seconds
+    pub fn process_inputs(
+        &mut self,
+        scene: &mut Scene,
+        event_pump: &mut EventPump,
+        mouse: &MouseUtil,
+        dt: FP,
+    ) {
+        match self {
+            Controller::FirstPerson(first_person) => {
+                first_person.process_inputs(&mut scene.camera, event_pump, mouse, dt)
+            }
+            Controller::Orbit(orbit) => orbit.process_inputs(scene, event_pump),
+        }
+    }
+}
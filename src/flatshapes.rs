@@ -1,15 +1,39 @@
 use sdl2::pixels::Color;
 use sdl2::rect::Point;
-use sdl2::render::WindowCanvas;
 
 use crate::math::*;
+use crate::pipeline::*;
+use crate::target::{DepthBuffer, RenderTarget};
+use crate::texture::*;
 
-pub fn put_color(canvas: &mut WindowCanvas, p: Point, c: Color) {
-    canvas.set_draw_color(c);
-    canvas.draw_point(p).unwrap();
+pub fn put_color(canvas: &mut impl RenderTarget, p: Point, c: Color) {
+    canvas.put_pixel(p, c);
 }
 
-pub fn draw_line(canvas: &mut WindowCanvas, p0: Point, p1: Point, c: Color) {
+// Square of `size` pixels centered on p, depth tested at `z` when a depth buffer is given
+pub fn draw_splat(
+    canvas: &mut impl RenderTarget,
+    mut depth: Option<&mut DepthBuffer>,
+    p: Point,
+    z: FP,
+    size: u32,
+    c: Color,
+) {
+    let start = p - Point::new(size as i32 / 2, size as i32 / 2);
+    for y in start.y..start.y + size as i32 {
+        for x in start.x..start.x + size as i32 {
+            let p = Point::new(x, y);
+            if let Some(depth) = depth.as_deref_mut() {
+                if !depth.test_and_set(p, z) {
+                    continue;
+                }
+            }
+            put_color(canvas, p, c);
+        }
+    }
+}
+
+pub fn draw_line(canvas: &mut impl RenderTarget, p0: Point, p1: Point, c: Color) {
     if (p1.x - p0.x).abs() > (p1.y - p0.y).abs() {
         let (p0, p1) = match p0.x > p1.x {
             true => (p1, p0),
@@ -31,129 +55,60 @@ pub fn draw_line(canvas: &mut WindowCanvas, p0: Point, p1: Point, c: Color) {
     }
 }
 
-pub fn draw_triangle(canvas: &mut WindowCanvas, p0: Point, p1: Point, p2: Point, c: Color) {
+pub fn draw_triangle(canvas: &mut impl RenderTarget, p0: Point, p1: Point, p2: Point, c: Color) {
     draw_line(canvas, p0, p1, c);
     draw_line(canvas, p0, p2, c);
     draw_line(canvas, p1, p2, c);
 }
 
 pub fn draw_filled_triangle(
-    canvas: &mut WindowCanvas,
+    canvas: &mut impl RenderTarget,
     p0: Point,
     p1: Point,
     p2: Point,
     outline_color: Color,
     fill_color: Color,
 ) {
-    // Sort points so that p0.y < p1.y < p2.y
-    let (p0, p1) = match p1.y < p0.y {
-        true => (p1, p0),
-        false => (p0, p1),
-    };
-    let (p0, p2) = match p2.y < p0.y {
-        true => (p2, p0),
-        false => (p0, p2),
-    };
-    let (p1, p2) = match p2.y < p1.y {
-        true => (p2, p1),
-        false => (p1, p2),
-    };
-    assert!(p0.y <= p1.y && p1.y <= p2.y);
-
-    // Create vecs of x coords of lines 01, 12, 02
-    // 02 is long, 01 + 12 are shorts
-    let mut x01_12 = lerp(p0.y, p0.x as FP, p1.y, p1.x as FP);
-    // End of 01 and start of 02 are same point
-    x01_12.pop();
-    // Combine 2 short sides
-    x01_12.append(&mut lerp(p1.y, p1.x as FP, p2.y, p2.x as FP));
-    let x02 = lerp(p0.y, p0.x as FP, p2.y, p2.x as FP);
-    // Make immutable
-    let x01_12 = x01_12;
-
-    // Find which side is left/right
-    let m = x02.len() / 2;
-    let (x_left, x_right) = match x02[m] < x01_12[m] {
-        true => (x02, x01_12),
-        false => (x01_12, x02),
-    };
-
-    for y in p0.y..p2.y {
-        let idx = (y - p0.y) as usize;
-        let x_l = x_left[idx] as i32;
-        let x_r = x_right[idx] as i32;
-        for x in x_l..x_r {
-            put_color(canvas, Point::new(x, y), fill_color);
-        }
-    }
+    let fill = |_: &Fragment<()>| Some(fill_color);
+    draw_varying_triangle(
+        canvas,
+        None,
+        Xyzv::at_pixel(p0, 1.0, ()),
+        Xyzv::at_pixel(p1, 1.0, ()),
+        Xyzv::at_pixel(p2, 1.0, ()),
+        &fill,
+    );
     draw_triangle(canvas, p0, p1, p2, outline_color);
 }
 
+// Scales the color by the intensity h
+fn shade(colorbase: Color, h: FP) -> Color {
+    let r = (colorbase.r as FP * h) as u8;
+    let g = (colorbase.g as FP * h) as u8;
+    let b = (colorbase.b as FP * h) as u8;
+    Color::from((r, g, b))
+}
+
 pub fn draw_shaded_triangle(
-    canvas: &mut WindowCanvas,
+    canvas: &mut impl RenderTarget,
     p0: Xyh,
     p1: Xyh,
     p2: Xyh,
     colorbase: Color,
 ) {
-    let (p0, p1) = match p1.y < p0.y {
-        true => (p1, p0),
-        false => (p0, p1),
-    };
-    let (p0, p2) = match p2.y < p0.y {
-        true => (p2, p0),
-        false => (p0, p2),
-    };
-    let (p1, p2) = match p2.y < p1.y {
-        true => (p2, p1),
-        false => (p1, p2),
-    };
-    assert!(p0.y <= p1.y && p1.y <= p2.y);
-
-    // Create vecs of x coords of lines 01, 12, 02
-    // 02 is long, 01 + 12 are shorts
-    let mut x01_12 = lerp(p0.y, p0.x as FP, p1.y, p1.x as FP);
-    // End of 01 and start of 02 are same point
-    x01_12.pop();
-    // Combine 2 short sides
-    x01_12.append(&mut lerp(p1.y, p1.x as FP, p2.y, p2.x as FP));
-    // Make immutable
-    let x01_12 = x01_12;
-    let x02 = lerp(p0.y, p0.x as FP, p2.y, p2.x as FP);
-
-    // Do the same for H
-    let mut h01_12 = lerp(p0.y, p0.h, p1.y, p1.h);
-    h01_12.pop();
-    h01_12.append(&mut lerp(p1.y, p1.h, p2.y, p2.h));
-    let h01_12 = h01_12;
-    let h02 = lerp(p0.y, p0.h, p2.y, p2.h as FP);
-
-    // Find which side is left/right
-    let m = x02.len() / 2;
-    let (x_left, h_left, x_right, h_right) = match x02[m] < x01_12[m] {
-        true => (x02, h02, x01_12, h01_12),
-        false => (x01_12, h01_12, x02, h02),
-    };
-
-    // Draw triangle
-    for y in p0.y..p2.y {
-        let idx = (y - p0.y) as usize;
-        let x_l = x_left[idx] as i32;
-        let x_r = x_right[idx] as i32;
-        let h_seg = lerp(x_l, h_left[idx], x_r, h_right[idx]);
-        for x in x_l..x_r {
-            let h = h_seg[(x - x_l) as usize];
-            let r = (colorbase.r as FP * h) as u8;
-            let g = (colorbase.g as FP * h) as u8;
-            let b = (colorbase.b as FP * h) as u8;
-            put_color(canvas, Point::new(x, y), Color::from((r, g, b)));
-        }
-    }
-    // draw_triangle(canvas, Point::new(p0.x, p0.y), Point::new(p1.x, p1.y), Point::new(p2.x, p2.y), colorbase);
+    let fragment = |f: &Fragment<FP>| Some(shade(colorbase, f.varying));
+    draw_varying_triangle(
+        canvas,
+        None,
+        Xyzv::new(p0.x as FP, p0.y as FP, 1.0, p0.h),
+        Xyzv::new(p1.x as FP, p1.y as FP, 1.0, p1.h),
+        Xyzv::new(p2.x as FP, p2.y as FP, 1.0, p2.h),
+        &fragment,
+    );
 }
 
 pub fn draw_multishade_triangle(
-    canvas: &mut WindowCanvas,
+    canvas: &mut impl RenderTarget,
     p0: Point,
     p1: Point,
     p2: Point,
@@ -161,59 +116,89 @@ pub fn draw_multishade_triangle(
     c1: Color,
     c2: Color,
 ) {
-    // Probably inefficient in so many ways
-    let c0 = Vec3::new(c0.r as FP, c0.g as FP, c0.b as FP);
-    let c1 = Vec3::new(c1.r as FP, c1.g as FP, c1.b as FP);
-    let c2 = Vec3::new(c2.r as FP, c2.g as FP, c2.b as FP);
-
-    let (p0, c0, p1, c1) = match p1.y < p0.y {
-        true => (p1, c1, p0, c0),
-        false => (p0, c0, p1, c1),
+    let vertex = |p: Point, c: Color| {
+        let c = Vec3::new(c.r as FP, c.g as FP, c.b as FP);
+        Xyzv::at_pixel(p, 1.0, c)
     };
-    let (p0, c0, p2, c2) = match p2.y < p0.y {
-        true => (p2, c2, p0, c0),
-        false => (p0, c0, p2, c2),
+    let fragment = |f: &Fragment<Vec3>| {
+        let c = f.varying;
+        Some(Color::from((c.x as u8, c.y as u8, c.z as u8)))
     };
-    let (p1, c1, p2, c2) = match p2.y < p1.y {
-        true => (p2, c2, p1, c1),
-        false => (p1, c1, p2, c2),
-    };
-
-    let mut x01_12 = lerp(p0.y, p0.x as FP, p1.y, p1.x as FP);
-    x01_12.pop();
-    x01_12.append(&mut lerp(p1.y, p1.x as FP, p2.y, p2.x as FP));
-    let x02 = lerp(p0.y, p0.x as FP, p2.y, p2.x as FP);
-    let x01_12 = x01_12;
+    draw_varying_triangle(
+        canvas,
+        None,
+        vertex(p0, c0),
+        vertex(p1, c1),
+        vertex(p2, c2),
+        &fragment,
+    );
+}
 
-    let mut c01_12 = (0..p1.y - p0.y)
-        .map(|x| c0.lerp(c1, x as FP / (p1.y - p0.y) as FP))
-        .collect::<Vec<Vec3>>();
-    c01_12.append(
-        &mut (0..=(p2.y - p1.y))
-            .map(|x| c1.lerp(c2, x as FP / (p2.y - p1.y) as FP))
-            .collect::<Vec<Vec3>>(),
+// 1/z is linear in screen space, the intensity is interpolated with perspective correction
+pub fn draw_depth_triangle(
+    canvas: &mut impl RenderTarget,
+    depth: &mut DepthBuffer,
+    p0: Xyzh,
+    p1: Xyzh,
+    p2: Xyzh,
+    colorbase: Color,
+) {
+    draw_varying_triangle(
+        canvas,
+        Some(depth),
+        Xyzv::new(p0.x, p0.y, p0.z, p0.h),
+        Xyzv::new(p1.x, p1.y, p1.z, p1.h),
+        Xyzv::new(p2.x, p2.y, p2.z, p2.h),
+        &depth_shader(colorbase),
     );
-    let c02 = (0..=(p2.y - p0.y))
-        .map(|x| c0.lerp(c2, x as FP / (p2.y - p0.y) as FP))
-        .collect::<Vec<Vec3>>();
-    let c01_12 = c01_12;
+}
 
-    let m = x02.len() / 2;
-    let (x_left, c_left, x_right, c_right) = match x02[m] < x01_12[m] {
-        true => (x02, c02, x01_12, c01_12),
-        false => (x01_12, c01_12, x02, c02),
-    };
+// Fragment shader of draw_depth_triangle
+pub fn depth_shader(colorbase: Color) -> impl Fn(&Fragment<FP>) -> Option<Color> + Copy {
+    move |f: &Fragment<FP>| Some(shade(colorbase, f.varying))
+}
 
-    for y in p0.y..p2.y {
-        let idx = (y - p0.y) as usize;
-        let x_l = x_left[idx] as i32;
-        let c_l = c_left[idx];
-        let x_r = x_right[idx] as i32;
-        let c_r = c_right[idx];
-        for x in x_l..x_r {
-            let c_vec = c_l.lerp(c_r, (x - x_l) as FP / (x_r - x_l) as FP);
-            let color = Color::from((c_vec.x as u8, c_vec.y as u8, c_vec.z as u8));
-            put_color(canvas, Point::new(x, y), color);
-        }
+// Texture coordinates are perspective correct, the mip level comes from how much they change
+// between neighbouring pixels
+#[allow(clippy::too_many_arguments)]
+pub fn draw_textured_triangle(
+    canvas: &mut impl RenderTarget,
+    depth: &mut DepthBuffer,
+    p0: Xyzuv,
+    p1: Xyzuv,
+    p2: Xyzuv,
+    texture: &Texture,
+    filter: Filter,
+    tint: Color,
+) {
+    let vertex = |p: Xyzuv| Xyzv::new(p.x, p.y, p.z, (p.uv, p.h));
+    draw_varying_triangle(
+        canvas,
+        Some(depth),
+        vertex(p0),
+        vertex(p1),
+        vertex(p2),
+        &textured_shader(texture, filter, tint),
+    );
+}
+
+// Fragment shader of draw_textured_triangle
+pub fn textured_shader(
+    texture: &Texture,
+    filter: Filter,
+    tint: Color,
+) -> impl Fn(&Fragment<(Vec2, FP)>) -> Option<Color> + Copy + '_ {
+    move |f: &Fragment<(Vec2, FP)>| {
+        let (uv, h) = f.varying;
+        let lod = texture.lod(f.right().0 - uv, f.below().0 - uv);
+        let color = modulate(texture.sample_lod(uv, lod, filter), tint);
+        Some(lerp_color(Color::BLACK, color, h))
     }
 }
+
+// Counterpart of textured_shader for surfaces without a texture, the coordinates are ignored
+pub fn untextured_shader(
+    colorbase: Color,
+) -> impl Fn(&Fragment<(Vec2, FP)>) -> Option<Color> + Copy {
+    move |f: &Fragment<(Vec2, FP)>| Some(shade(colorbase, f.varying.1))
+}
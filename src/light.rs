@@ -0,0 +1,158 @@
+use crate::math::*;
+
+// Intensity falls off as 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: FP,
+    pub linear: FP,
+    pub quadratic: FP,
+}
+
+impl Attenuation {
+    pub const NONE: Attenuation = Attenuation {
+        constant: 1.0,
+        linear: 0.0,
+        quadratic: 0.0,
+    };
+    pub fn factor(&self, distance: FP) -> FP {
+        1.0 / (self.constant + self.linear * distance + self.quadratic * distance * distance)
+    }
+}
+
+impl Default for Attenuation {
+    fn default() -> Attenuation {
+        Attenuation::NONE
+    }
+}
+
+// Everything is in world space, directions are the way the light travels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    Ambient {
+        intensity: FP,
+    },
+    Directional {
+        dir: Vec3,
+        intensity: FP,
+    },
+    Point {
+        pos: Vec3,
+        intensity: FP,
+        attenuation: Attenuation,
+    },
+    // Fully lit within `inner` radians of `dir`, fading out up to `outer`
+    Spot {
+        pos: Vec3,
+        dir: Vec3,
+        inner: FP,
+        outer: FP,
+        intensity: FP,
+        attenuation: Attenuation,
+    },
+}
+
+impl Light {
+    // Direction towards the light (None for ambient light) and how much of it reaches `pos`
+    fn incoming(&self, pos: Vec3) -> (Option<Vec3>, FP) {
+        match *self {
+            Light::Ambient { intensity } => (None, intensity),
+            Light::Directional { dir, intensity } => (Some(-dir.normalize_or_zero()), intensity),
+            Light::Point {
+                pos: light_pos,
+                intensity,
+                attenuation,
+            } => {
+                let to_light = light_pos - pos;
+                let amount = intensity * attenuation.factor(to_light.length());
+                (Some(to_light.normalize_or_zero()), amount)
+            }
+            Light::Spot {
+                pos: light_pos,
+                dir,
+                inner,
+                outer,
+                intensity,
+                attenuation,
+            } => {
+                let to_light = light_pos - pos;
+                let distance = to_light.length();
+                let to_light = to_light.normalize_or_zero();
+                let angle = (-to_light)
+                    .dot(dir.normalize_or_zero())
+                    .clamp(-1.0, 1.0)
+                    .acos();
+                let cone = match outer > inner {
+                    true => ((outer - angle) / (outer - inner)).clamp(0.0, 1.0),
+                    false => (angle <= outer) as u8 as FP,
+                };
+                (
+                    Some(to_light),
+                    intensity * cone * attenuation.factor(distance),
+                )
+            }
+        }
+    }
+    // Diffuse light reaching a surface at `pos` facing `normal`, which must be normalized
+    pub fn intensity_at(&self, pos: Vec3, normal: Vec3) -> FP {
+        match self.incoming(pos) {
+            (Some(to_light), amount) => amount * normal.dot(to_light).max(0.0),
+            (None, amount) => amount,
+        }
+    }
+}
+
+// Lights up whatever the camera at `eye` looks at, for scenes without lights of their own
+pub fn headlight(eye: Vec3) -> [Light; 2] {
+    [
+        Light::Ambient { intensity: 0.2 },
+        Light::Point {
+            pos: eye,
+            intensity: 0.8,
+            attenuation: Attenuation::NONE,
+        },
+    ]
+}
+
+// Total intensity from all lights, clamped to what the rasterizer can show
+pub fn illuminate(lights: &[Light], pos: Vec3, normal: Vec3) -> FP {
+    lights
+        .iter()
+        .map(|light| light.intensity_at(pos, normal))
+        .sum::<FP>()
+        .clamp(0.0, 1.0)
+}
+
+// Unclamped sums of the light terms, the diffuse one includes ambient light
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lighting {
+    pub diffuse: FP,
+    pub specular: FP,
+}
+
+// Blinn-Phong, with the specular term from the angle between the normal and the vector halfway
+// between the light and the eye. All vectors must be normalized.
+pub fn blinn_phong(
+    lights: &[Light],
+    pos: Vec3,
+    normal: Vec3,
+    to_eye: Vec3,
+    shininess: FP,
+) -> Lighting {
+    let mut lighting = Lighting::default();
+    for light in lights {
+        match light.incoming(pos) {
+            (Some(to_light), amount) => {
+                let lambert = normal.dot(to_light);
+                // Nothing reaches faces turned away, not even a highlight
+                if lambert <= 0.0 {
+                    continue;
+                }
+                let half = (to_light + to_eye).normalize_or_zero();
+                lighting.diffuse += amount * lambert;
+                lighting.specular += amount * normal.dot(half).max(0.0).powf(shininess);
+            }
+            (None, amount) => lighting.diffuse += amount,
+        }
+    }
+    lighting
+}
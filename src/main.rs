@@ -1,33 +1,53 @@
 #![feature(test)]
+#![feature(portable_simd)]
 #![allow(dead_code)]
 
+use std::path::Path;
 use std::rc::Rc;
 use std::time;
 
 use nanorand::{Rng, WyRand};
 
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Point;
 
 mod camera;
+mod clip;
+mod controls;
 mod flatshapes;
+mod light;
+mod material;
 mod math;
 mod meshes;
+mod multiview;
+mod obj;
 mod object;
+mod pipeline;
+mod ply;
+mod scene;
+mod shaders;
+mod soa;
+mod stl;
+mod target;
+mod texture;
+mod tiles;
 
 use camera::*;
+use controls::*;
 use flatshapes::*;
+use light::*;
+use material::Material;
 use math::*;
 use meshes::*;
+use multiview::MultiView;
 use object::*;
+use scene::Scene;
+use target::{present, DepthBuffer, Framebuffer, RenderTarget};
 
 #[cfg(test)]
 mod bench;
-
-struct Scene {
-    camera: Camera,
-    objects: Vec<Object>,
-}
+#[cfg(test)]
+mod tests;
 
 enum RenderMode {
     Point,
@@ -37,6 +57,9 @@ enum RenderMode {
     ShadedTriangle,
     MultishadedTriangle,
     Mesh,
+    SolidMesh,
+    // Wireframe from the front, top, side and a perspective camera at once
+    MultiView,
 }
 
 fn rand_percent(rng: &mut WyRand) -> FP {
@@ -44,17 +67,31 @@ fn rand_percent(rng: &mut WyRand) -> FP {
 }
 
 fn project_vertex(camera: &Camera, point: &Vec3) -> Option<Point> {
-    // Switch to camera space
-    let point_local = camera.rot * (*point - camera.pos);
-
-    if point_local.z <= camera.viewport.z {
+    let v = clip::to_clip(camera, point);
+    if clip::ClipPlane::Near.distance(v) <= 0.0 {
         return None;
     }
-    // camera.viewport.z / point_local.z  projects to viewport
-    let vp_res = point_local.xy() * camera.viewport.z / point_local.z;
-    // camera.viewport.w scales to canvas
-    let res = (vp_res + (camera.viewport.xy() / 2.0)) * camera.viewport.w;
-    Some(Point::new(res.x as i32, res.y as i32))
+    Some(clip::clip_to_screen(camera, v).0)
+}
+
+// Picks a loader based on the file extension, defaulting to glTF
+fn load_objects(path: &Path) -> Result<Vec<Object>, LoadError> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("obj") => {
+            let file = obj::load_obj(path)?;
+            let materials = file.load_materials(path.parent().unwrap_or_else(|| Path::new("")));
+            Ok(file.into_objects(&materials))
+        }
+        Some(ext) if ext.eq_ignore_ascii_case("ply") => Ok(vec![Object::from_matrix(
+            Rc::new(ply::load_ply(path)?),
+            Mat4::from_quat(Y_UP_TO_WORLD),
+        )]),
+        Some(ext) if ext.eq_ignore_ascii_case("stl") => Ok(vec![Object::from_matrix(
+            Rc::new(stl::load_stl(path)?),
+            Mat4::from_quat(Z_UP_TO_WORLD),
+        )]),
+        _ => import_scene(path),
+    }
 }
 
 pub fn main() {
@@ -69,37 +106,110 @@ pub fn main() {
         .resizable()
         .build()
         .unwrap();
-    // sdl_context.mouse().set_relative_mouse_mode(true);
+    let mouse = sdl_context.mouse();
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut canvas = window.into_canvas().build().unwrap();
     canvas.set_draw_color(Color::WHITE);
     canvas.clear();
     canvas.present(); // Leave white canvas while the rest of the program inits
 
+    // Everything is drawn into `frame` and then uploaded in one go
+    let texture_creator = canvas.texture_creator();
+    let new_texture = |(width, height)| {
+        texture_creator
+            .create_texture_streaming(PixelFormatEnum::ARGB8888, width, height)
+            .unwrap()
+    };
+    let mut frame = Framebuffer::new(800, 600);
+    frame.clear(Color::WHITE);
+    let mut texture = new_texture(frame.size());
+    let mut depth = DepthBuffer::new(800, 600);
+
     let mut rng = WyRand::new();
-    let mut camera = Camera::new(
+    let camera = Camera::new(
         Some(Vec3::new(0.0, 0.0, -10.0)),
         None,
         Some(90u8),
         (800, 600),
     );
-    // let mut imp_mesh = import_mesh(Path::new("src/teapot.glb"));
-    // imp_mesh.truncate(imp_mesh.len() - (imp_mesh.len() % 3));
-    let obj = Object {
-        pos: Vec3::new(0.0, 3.0, 0.0),
-        rot: Quat::default(),
-        scale: 4.0,
-        mesh: Rc::new(teapot()),
+    // Load a model if one was passed on the command line, otherwise show the teapot
+    let path = std::env::args().nth(1);
+    let objects = match &path {
+        Some(path) => load_objects(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Could not load {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => vec![Object {
+            transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::new(0.0, 3.0, 0.0)),
+            material: Material {
+                color: Color::GREEN,
+                specular: 0.6,
+                ..Material::default()
+            },
+            ..Object::new(Rc::new(teapot()))
+        }],
+    };
+    let lights = vec![
+        Light::Ambient { intensity: 0.15 },
+        Light::Directional {
+            dir: Vec3::new(-1.0, 1.0, 2.0),
+            intensity: 0.6,
+        },
+        Light::Point {
+            pos: Vec3::new(6.0, -6.0, -6.0),
+            intensity: 0.5,
+            attenuation: Attenuation {
+                linear: 0.02,
+                ..Attenuation::NONE
+            },
+        },
+    ];
+    let mut scene = Scene {
+        camera,
+        objects,
+        lights,
+        threads: 0,
+    };
+    for obj in &mut scene.objects {
+        obj.cull = CullMode::Back;
+        obj.shading = Shading::Phong;
+    }
+    // Loaded models are inspected by orbiting around them
+    let mut controller = match (&path, scene.bounds()) {
+        (Some(_), Some((min, max))) => {
+            let mut orbit = Orbit::new(&scene.camera, Vec3::ZERO);
+            orbit.frame(&mut scene.camera, min, max);
+            Controller::Orbit(orbit)
+        }
+        _ => Controller::FirstPerson(FirstPerson::new(&scene.camera)),
     };
+    let mut views = MultiView::new(scene.camera, frame.size());
+    if let Some((min, max)) = scene.bounds() {
+        views.frame(min, max);
+    }
 
+    let mut last_frame = time::Instant::now();
     loop {
         let fr_start = time::Instant::now();
+        // Capped so that nothing jumps after the window was stuck, e.g. while being dragged
+        let dt = (fr_start - last_frame).as_secs_f64().min(0.1) as FP;
+        last_frame = fr_start;
 
-        camera.process_inputs(&mut event_pump);
+        match render_mode {
+            RenderMode::MultiView => views.process_inputs(&mut event_pump, &mouse, dt),
+            _ => controller.process_inputs(&mut scene, &mut event_pump, &mouse, dt),
+        }
+        let size = canvas.output_size().unwrap();
+        if frame.size() != size {
+            frame.resize(size.0, size.1);
+            frame.clear(Color::WHITE);
+            depth.resize(size.0, size.1);
+            texture = new_texture(size);
+        }
 
         match render_mode {
             RenderMode::Point => put_color(
-                &mut canvas,
+                &mut frame,
                 Point::new(
                     rng.generate_range(1_u32..=800) as i32,
                     rng.generate_range(1_u32..=600) as i32,
@@ -107,7 +217,7 @@ pub fn main() {
                 Color::BLACK,
             ),
             RenderMode::Line => draw_line(
-                &mut canvas,
+                &mut frame,
                 Point::new(400, 300),
                 Point::new(
                     rng.generate_range(100_u32..=700) as i32,
@@ -117,7 +227,7 @@ pub fn main() {
             ),
             RenderMode::Triangle => {
                 draw_triangle(
-                    &mut canvas,
+                    &mut frame,
                     Point::new(
                         rng.generate_range(100_u32..=700) as i32,
                         rng.generate_range(100_u32..=500) as i32,
@@ -135,7 +245,7 @@ pub fn main() {
             }
             RenderMode::FilledTriangle => {
                 draw_filled_triangle(
-                    &mut canvas,
+                    &mut frame,
                     Point::new(
                         rng.generate_range(100_u32..=700) as i32,
                         rng.generate_range(100_u32..=500) as i32,
@@ -154,7 +264,7 @@ pub fn main() {
             }
             RenderMode::ShadedTriangle => {
                 draw_shaded_triangle(
-                    &mut canvas,
+                    &mut frame,
                     Xyh::new(
                         rng.generate_range(100_u32..=700) as i32,
                         rng.generate_range(100_u32..=500) as i32,
@@ -179,7 +289,7 @@ pub fn main() {
             }
             RenderMode::MultishadedTriangle => {
                 draw_multishade_triangle(
-                    &mut canvas,
+                    &mut frame,
                     Point::new(
                         rng.generate_range(100_u32..=700) as i32,
                         rng.generate_range(100_u32..=500) as i32,
@@ -198,13 +308,21 @@ pub fn main() {
                 );
             }
             RenderMode::Mesh => {
-                // camera.rot.normalize();
-                canvas.set_draw_color(Color::WHITE);
-                canvas.clear();
-                obj.render(&mut canvas, &camera);
+                // scene.camera.rot.normalize();
+                frame.clear(Color::WHITE);
+                scene.render(&mut frame);
+            }
+            RenderMode::SolidMesh => {
+                depth.clear();
+                frame.clear(Color::WHITE);
+                scene.render_tiled(&mut frame, &mut depth);
+            }
+            RenderMode::MultiView => {
+                frame.clear(Color::WHITE);
+                views.render(&scene, &mut frame);
             }
         };
-        canvas.present();
+        present(&mut canvas, &mut texture, &frame);
         // Comment out for UNLIMITED FPS!!
         std::thread::sleep(
             time::Duration::from_millis(1000 / 60)
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use sdl2::pixels::Color;
+
+use crate::math::FP;
+use crate::texture::*;
+
+#[derive(Clone)]
+pub struct Material {
+    // Multiplied with the texture when there is one
+    pub color: Color,
+    pub texture: Option<Arc<Texture>>,
+    pub filter: Filter,
+    // Blinn-Phong reflectance, only per pixel shading has specular highlights
+    pub diffuse: FP,
+    pub specular: FP,
+    pub shininess: FP,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            color: Color::WHITE,
+            texture: None,
+            filter: Filter::Trilinear,
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 32.0,
+        }
+    }
+}
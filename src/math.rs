@@ -2,19 +2,84 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::ops;
 
-use glam::{DQuat, DVec2, DVec3, DVec4};
-pub use glam::{IVec2, IVec3};
+pub use glam::IVec2;
 pub use glam::{Vec3Swizzles, Vec4Swizzles};
 
-// Annoying way to change float precision easily
-pub type FP = f64;
-pub use std::f64::consts::*;
+// Float precision of the whole crate, f64 unless the "f32" feature is enabled.
+// The f32 types are the SIMD aligned ones.
+// The matrices take and return PlainVec3, which needs converting with plain() and aligned().
+// Loaded files are in f32 and go through the *_from_f32 functions.
+#[cfg(not(feature = "f32"))]
+mod precision {
+    pub use glam::{DAffine3 as Affine3, DMat3 as Mat3, DMat4 as Mat4, DQuat as Quat};
+    pub use glam::{DVec2 as Vec2, DVec3 as Vec3, DVec4 as Vec4};
+    pub use std::f64::consts::*;
+    pub type FP = f64;
+    pub type PlainVec3 = Vec3;
 
-pub type Vec2 = DVec2;
-pub type Vec3 = DVec3;
-pub type Vec4 = DVec4;
+    pub fn plain(v: Vec3) -> PlainVec3 {
+        v
+    }
+    pub fn aligned(v: PlainVec3) -> Vec3 {
+        v
+    }
+    pub fn vec2_from_f32(v: glam::Vec2) -> Vec2 {
+        v.as_dvec2()
+    }
+    pub fn vec3_from_f32(v: glam::Vec3) -> Vec3 {
+        v.as_dvec3()
+    }
+    pub fn mat4_from_f32(m: glam::Mat4) -> Mat4 {
+        m.as_dmat4()
+    }
+}
 
-pub type Quat = DQuat;
+#[cfg(feature = "f32")]
+mod precision {
+    pub use glam::{Affine3A as Affine3, Mat3, Mat4, Quat};
+    pub use glam::{Vec2, Vec3A as Vec3, Vec4};
+    pub use std::f32::consts::*;
+    pub type FP = f32;
+    pub type PlainVec3 = glam::Vec3;
+
+    pub fn plain(v: Vec3) -> PlainVec3 {
+        v.into()
+    }
+    pub fn aligned(v: PlainVec3) -> Vec3 {
+        v.into()
+    }
+    pub fn vec2_from_f32(v: glam::Vec2) -> Vec2 {
+        v
+    }
+    pub fn vec3_from_f32(v: glam::Vec3) -> Vec3 {
+        v.into()
+    }
+    pub fn mat4_from_f32(m: glam::Mat4) -> Mat4 {
+        m
+    }
+}
+
+pub use precision::*;
+
+// Scales, then rotates, then translates
+pub fn affine(scale: Vec3, rot: Quat, translation: Vec3) -> Affine3 {
+    Affine3::from_scale_rotation_translation(plain(scale), rot, plain(translation))
+}
+
+pub fn transform_point(transform: &Affine3, p: Vec3) -> Vec3 {
+    transform.matrix3 * p + transform.translation
+}
+
+pub fn transform_vector(transform: &Affine3, v: Vec3) -> Vec3 {
+    transform.matrix3 * v
+}
+
+// Normals go through the inverse transpose to stay perpendicular to the surface under
+// non-uniform scale. Takes the inverse of the transform, the result is not normalized.
+pub fn transform_normal(inverse: &Affine3, n: Vec3) -> Vec3 {
+    let m = &inverse.matrix3;
+    Vec3::new(m.x_axis.dot(n), m.y_axis.dot(n), m.z_axis.dot(n))
+}
 
 pub struct Xyh {
     pub x: i32,
@@ -28,6 +93,93 @@ impl Xyh {
     }
 }
 
+// Screen point with inverse depth (1/z) and intensity, positioned within the pixel
+pub struct Xyzh {
+    pub x: FP,
+    pub y: FP,
+    pub z: FP,
+    pub h: FP,
+}
+
+impl Xyzh {
+    pub fn new(x: FP, y: FP, z: FP, h: FP) -> Xyzh {
+        Xyzh { x, y, z, h }
+    }
+}
+
+// Screen point with inverse depth (1/z), texture coordinates and intensity
+pub struct Xyzuv {
+    pub x: FP,
+    pub y: FP,
+    pub z: FP,
+    pub uv: Vec2,
+    pub h: FP,
+}
+
+impl Xyzuv {
+    pub fn new(x: FP, y: FP, z: FP, uv: Vec2, h: FP) -> Xyzuv {
+        Xyzuv { x, y, z, uv, h }
+    }
+}
+
+// World space position, normal and texture coordinates of a point on a surface
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Surface {
+    pub pos: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+}
+
+// Linear interpolation for anything carried along with vertices
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: FP) -> Self;
+}
+
+// Nothing to interpolate
+impl Lerp for () {
+    fn lerp(self, _: (), _: FP) {}
+}
+
+impl Lerp for FP {
+    fn lerp(self, other: FP, t: FP) -> FP {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vec2 {
+    fn lerp(self, other: Vec2, t: FP) -> Vec2 {
+        Vec2::lerp(self, other, t)
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(self, other: Vec3, t: FP) -> Vec3 {
+        Vec3::lerp(self, other, t)
+    }
+}
+
+impl Lerp for Vec4 {
+    fn lerp(self, other: Vec4, t: FP) -> Vec4 {
+        Vec4::lerp(self, other, t)
+    }
+}
+
+impl Lerp for Surface {
+    fn lerp(self, other: Surface, t: FP) -> Surface {
+        Surface {
+            pos: self.pos.lerp(other.pos, t),
+            normal: self.normal.lerp(other.normal, t),
+            uv: self.uv.lerp(other.uv, t),
+        }
+    }
+}
+
+impl<A: Lerp, B: Lerp> Lerp for (A, B) {
+    fn lerp(self, other: (A, B), t: FP) -> (A, B) {
+        (self.0.lerp(other.0, t), self.1.lerp(other.1, t))
+    }
+}
+
 macro_rules! assert_eq_fp {
     ($lhs:expr, $rhs:expr) => {
         assert!(($lhs - $rhs).abs() < FP::EPSILON);
@@ -1,13 +1,247 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use gltf::json::mesh::Mode;
-use gltf::Primitive;
+use gltf::texture::{MagFilter, MinFilter};
+use gltf::{Node, Primitive};
+use sdl2::pixels::Color;
 
-use crate::math::Vec3;
+use crate::material::Material;
+use crate::math::*;
+use crate::object::Object;
+use crate::texture::{Filter, Texture};
+
+// glTF and most other formats are +Y up and look down -Z,
+// a half turn around X matches our +Y down and +Z forward
+pub const Y_UP_TO_WORLD: Quat = Quat::from_xyzw(1.0, 0.0, 0.0, 0.0);
+// STL and other CAD/3D printing formats are +Z up, a quarter turn around X moves that to -Y
+pub const Z_UP_TO_WORLD: Quat = Quat::from_xyzw(FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2);
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(String),
+    UnsupportedMode(Mode),
+    MissingAttribute(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "I/O error: {}", e),
+            LoadError::Parse(msg) => write!(f, "parse error: {}", msg),
+            LoadError::UnsupportedMode(mode) => write!(f, "unsupported primitive mode {:?}", mode),
+            LoadError::MissingAttribute(name) => write!(f, "missing {} attribute", name),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for LoadError {
+    fn from(e: image::ImageError) -> LoadError {
+        match e {
+            image::ImageError::IoError(e) => LoadError::Io(e),
+            e => LoadError::Parse(e.to_string()),
+        }
+    }
+}
+
+impl From<gltf::Error> for LoadError {
+    fn from(e: gltf::Error) -> LoadError {
+        match e {
+            gltf::Error::Io(e) => LoadError::Io(e),
+            e => LoadError::Parse(e.to_string()),
+        }
+    }
+}
+
+// Indexed triangle mesh, every 3 indices make up one triangle.
+// Attributes are per-vertex and so have the same length as `vertices` when present.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<u32>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<Vec2>>,
+    pub colors: Option<Vec<Color>>,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices.iter().all(|&idx| (idx as usize) < vertices.len()));
+        Mesh {
+            vertices,
+            indices,
+            ..Mesh::default()
+        }
+    }
+    // Builds an indexed mesh out of a triangle soup, merging identical positions
+    pub fn from_soup(soup: &[Vec3]) -> Mesh {
+        assert_eq!(soup.len() % 3, 0);
+        let mut vertices = Vec::new();
+        let mut seen: HashMap<u64, u32> = HashMap::new();
+        let indices = soup
+            .iter()
+            .map(|v| match seen.get(&vec3_hash(v)) {
+                Some(&idx) if vertices[idx as usize] == *v => idx,
+                _ => {
+                    let idx = vertices.len() as u32;
+                    vertices.push(*v);
+                    seen.insert(vec3_hash(v), idx);
+                    idx
+                }
+            })
+            .collect();
+        Mesh::new(vertices, indices)
+    }
+    // Checks the invariants that `Mesh::new` asserts, for meshes built from untrusted data
+    pub fn validate(&self) -> Result<(), LoadError> {
+        if !self.indices.len().is_multiple_of(3) {
+            return Err(LoadError::Parse(format!(
+                "index count {} is not a multiple of 3",
+                self.indices.len()
+            )));
+        }
+        if let Some(&idx) = self
+            .indices
+            .iter()
+            .find(|&&idx| idx as usize >= self.vertices.len())
+        {
+            return Err(LoadError::Parse(format!(
+                "index {} out of range for {} vertices",
+                idx,
+                self.vertices.len()
+            )));
+        }
+        let attribute_lens = [
+            ("normals", self.normals.as_ref().map(Vec::len)),
+            ("uvs", self.uvs.as_ref().map(Vec::len)),
+            ("colors", self.colors.as_ref().map(Vec::len)),
+        ];
+        for (name, len) in attribute_lens {
+            if let Some(len) = len.filter(|&len| len != self.vertices.len()) {
+                return Err(LoadError::Parse(format!(
+                    "{} {} for {} vertices",
+                    len,
+                    name,
+                    self.vertices.len()
+                )));
+            }
+        }
+        Ok(())
+    }
+    // Smooth normals from the faces around each vertex, weighted by their angle at the vertex
+    // so that the way faces are split into triangles does not matter.
+    // They point out of counter-clockwise faces.
+    pub fn computed_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for tri in self.triangles() {
+            let [v_a, v_b, v_c] = tri.map(|idx| self.vertices[idx]);
+            let face = (v_b - v_a).cross(v_c - v_a).normalize_or_zero();
+            for (idx, (prev, next)) in tri.iter().zip([(v_c, v_b), (v_a, v_c), (v_b, v_a)]) {
+                let corner = self.vertices[*idx];
+                normals[*idx] += face * (prev - corner).angle_between(next - corner);
+            }
+        }
+        normals.iter().map(|n| n.normalize_or_zero()).collect()
+    }
+    // Vertices without any triangles, rendered as points
+    pub fn is_point_cloud(&self) -> bool {
+        self.indices.is_empty() && !self.vertices.is_empty()
+    }
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+    pub fn triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.indices
+            .chunks_exact(3)
+            .map(|tri| [tri[0] as usize, tri[1] as usize, tri[2] as usize])
+    }
+}
+
+// Splits a simple (possibly concave) polygon into triangles by ear clipping.
+// Returns indices into `polygon`, keeping its winding order.
+pub fn triangulate_polygon(polygon: &[Vec3]) -> Vec<[usize; 3]> {
+    let n = polygon.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    // Newell's method, works for non-planar and concave polygons
+    let normal = (0..n).fold(Vec3::ZERO, |acc, idx| {
+        let (a, b) = (polygon[idx], polygon[(idx + 1) % n]);
+        acc + Vec3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        )
+    });
+    let inside = |p: Vec3, a: Vec3, b: Vec3, c: Vec3| {
+        (b - a).cross(p - a).dot(normal) >= 0.0
+            && (c - b).cross(p - b).dot(normal) >= 0.0
+            && (a - c).cross(p - c).dot(normal) >= 0.0
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let corner = |idx: usize| {
+            (
+                remaining[(idx + m - 1) % m],
+                remaining[idx],
+                remaining[(idx + 1) % m],
+            )
+        };
+        let ear = (0..m).find(|&idx| {
+            let (a, b, c) = corner(idx);
+            let (pa, pb, pc) = (polygon[a], polygon[b], polygon[c]);
+            (pb - pa).cross(pc - pb).dot(normal) > 0.0
+                && !remaining
+                    .iter()
+                    .any(|&other| ![a, b, c].contains(&other) && inside(polygon[other], pa, pb, pc))
+        });
+        match ear {
+            Some(idx) => {
+                let (a, b, c) = corner(idx);
+                triangles.push([a, b, c]);
+                remaining.remove(idx);
+            }
+            // Degenerate or self-intersecting, fall back to a fan for what is left
+            None => break,
+        }
+    }
+    for idx in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[idx], remaining[idx + 1]]);
+    }
+    triangles
+}
 
 // Adapted from https://github.com/kretash/UtahTeapot/blob/master/teapot.h
-pub fn teapot() -> Vec<Vec3> {
-    vec![
+// Some of the values can't be told apart in f32
+#[allow(clippy::excessive_precision)]
+pub fn teapot() -> Mesh {
+    Mesh::from_soup(&[
         Vec3::new(0.700000, -1.200000, 0.000000),
         Vec3::new(0.605600, -1.200000, -0.355700),
         Vec3::new(0.598800, -1.243700, -0.351700),
@@ -1736,12 +1970,12 @@ pub fn teapot() -> Vec<Vec3> {
         Vec3::new(0.650000, -1.200000, 0.000000),
         Vec3::new(0.562400, -1.200000, 0.330300),
         Vec3::new(0.458200, -1.255600, 0.269100),
-    ]
+    ])
 }
 
-pub fn cube() -> Vec<Vec3> {
+pub fn cube() -> Mesh {
     let scale = 1.0;
-    let verts: Vec<Vec3> = vec![
+    let verts = vec![
         Vec3::new(-scale, -scale, -scale),
         Vec3::new(scale, -scale, -scale),
         Vec3::new(scale, scale, -scale),
@@ -1751,22 +1985,148 @@ pub fn cube() -> Vec<Vec3> {
         Vec3::new(scale, scale, scale),
         Vec3::new(-scale, scale, scale),
     ];
-    vec![
-        verts[0], verts[1], verts[2], verts[0], verts[2], verts[3], verts[4], verts[0], verts[3],
-        verts[4], verts[3], verts[7], verts[5], verts[4], verts[7], verts[5], verts[7], verts[6],
-        verts[1], verts[5], verts[6], verts[1], verts[6], verts[2], verts[4], verts[5], verts[1],
-        verts[4], verts[1], verts[0], verts[2], verts[6], verts[7], verts[2], verts[7], verts[3],
-    ]
+    Mesh::new(
+        verts,
+        vec![
+            0, 2, 1, 0, 3, 2, 4, 3, 0, 4, 7, 3, 5, 7, 4, 5, 6, 7, 1, 6, 5, 1, 2, 6, 4, 1, 5, 4, 0,
+            1, 2, 7, 6, 2, 3, 7,
+        ],
+    )
+}
+
+pub fn import_mesh(path: &Path) -> Result<Mesh, LoadError> {
+    let (loaded, buffers, _) = gltf::import(path)?;
+    let gl_primitive: Primitive = loaded
+        .meshes()
+        .next()
+        .and_then(|m| m.primitives().next())
+        .ok_or_else(|| LoadError::Parse("file contains no meshes".to_string()))?;
+    read_primitive(&gl_primitive, &buffers, 0)
+}
+
+// Imports every mesh instanced by the default scene (or the first one if there is no default),
+// placed according to the node hierarchy. Meshes used by several nodes are shared between objects.
+pub fn import_scene(path: &Path) -> Result<Vec<Object>, LoadError> {
+    let (loaded, buffers, images) = gltf::import(path)?;
+    let scene = loaded
+        .default_scene()
+        .or_else(|| loaded.scenes().next())
+        .ok_or_else(|| LoadError::Parse("file contains no scenes".to_string()))?;
+    let root = Mat4::from_quat(Y_UP_TO_WORLD);
+    let mut meshes = HashMap::new();
+    let mut textures = HashMap::new();
+    let mut objects = Vec::new();
+    for node in scene.nodes() {
+        import_node(
+            &node,
+            root,
+            (&buffers, &images),
+            &mut meshes,
+            &mut textures,
+            &mut objects,
+        )?;
+    }
+    Ok(objects)
+}
+
+fn import_node(
+    node: &Node,
+    parent: Mat4,
+    data: (&[gltf::buffer::Data], &[gltf::image::Data]),
+    meshes: &mut HashMap<(usize, usize), Rc<Mesh>>,
+    textures: &mut HashMap<usize, Arc<Texture>>,
+    objects: &mut Vec<Object>,
+) -> Result<(), LoadError> {
+    let (buffers, images) = data;
+    let local = mat4_from_f32(glam::f32::Mat4::from_cols_array_2d(
+        &node.transform().matrix(),
+    ));
+    let transform = parent * local;
+    if let Some(gl_mesh) = node.mesh() {
+        for gl_primitive in gl_mesh.primitives() {
+            let pbr = gl_primitive.material().pbr_metallic_roughness();
+            let base_texture = pbr.base_color_texture();
+            // The base color texture picks which set of texture coordinates it uses
+            let uv_set = base_texture.as_ref().map_or(0, |info| info.tex_coord());
+            let mesh = match meshes.entry((gl_mesh.index(), gl_primitive.index())) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => entry
+                    .insert(Rc::new(read_primitive(&gl_primitive, buffers, uv_set)?))
+                    .clone(),
+            };
+            let [r, g, b, a] = pbr.base_color_factor().map(|c| (c * 255.0).round() as u8);
+            let mut material = Material {
+                color: Color::RGBA(r, g, b, a),
+                ..Material::default()
+            };
+            if let Some(info) = base_texture {
+                let gl_texture = info.texture();
+                let image_idx = gl_texture.source().index();
+                let texture = match textures.entry(image_idx) {
+                    Entry::Occupied(entry) => entry.get().clone(),
+                    Entry::Vacant(entry) => {
+                        let image = images.get(image_idx).ok_or_else(|| {
+                            LoadError::Parse(format!("missing image {}", image_idx))
+                        })?;
+                        entry.insert(Arc::new(Texture::from_gltf(image)?)).clone()
+                    }
+                };
+                material.texture = Some(texture);
+                let sampler = gl_texture.sampler();
+                // Only blending between mip levels is left to trilinear, unset means anything goes
+                material.filter = match (sampler.mag_filter(), sampler.min_filter()) {
+                    (Some(MagFilter::Nearest), _) => Filter::Nearest,
+                    (_, Some(MinFilter::NearestMipmapLinear))
+                    | (_, Some(MinFilter::LinearMipmapLinear))
+                    | (_, None) => Filter::Trilinear,
+                    _ => Filter::Bilinear,
+                };
+            }
+            objects.push(Object {
+                material,
+                ..Object::from_matrix(mesh, transform)
+            });
+        }
+    }
+    for child in node.children() {
+        import_node(&child, transform, data, meshes, textures, objects)?;
+    }
+    Ok(())
 }
 
-pub fn import_mesh(path: &Path) -> Vec<Vec3> {
-    let (loaded, buffers, _) = gltf::import(path).unwrap();
-    let gl_primitive: Primitive = loaded.meshes().next().unwrap().primitives().next().unwrap();
-    assert_eq!(gl_primitive.mode(), Mode::Triangles);
-    let pos = gl_primitive
-        .reader(|buf| Some(&buffers[buf.index()]))
+fn read_primitive(
+    gl_primitive: &Primitive,
+    buffers: &[gltf::buffer::Data],
+    uv_set: u32,
+) -> Result<Mesh, LoadError> {
+    if gl_primitive.mode() != Mode::Triangles {
+        return Err(LoadError::UnsupportedMode(gl_primitive.mode()));
+    }
+    let reader = gl_primitive.reader(|buf| buffers.get(buf.index()).map(|data| &data[..]));
+    let vertices: Vec<Vec3> = reader
         .read_positions()
-        .unwrap();
-    pos.map(|v| glam::f32::Vec3::from_slice(&v).as_dvec3())
-        .collect()
+        .ok_or(LoadError::MissingAttribute("POSITION"))?
+        .map(|v| vec3_from_f32(v.into()))
+        .collect();
+    let indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+    let mesh = Mesh {
+        vertices,
+        indices,
+        normals: reader
+            .read_normals()
+            .map(|n| n.map(|v| vec3_from_f32(v.into())).collect()),
+        uvs: reader
+            .read_tex_coords(uv_set)
+            .map(|uv| uv.into_f32().map(|v| vec2_from_f32(v.into())).collect()),
+        colors: reader.read_colors(0).map(|c| {
+            c.into_rgba_u8()
+                .map(|[r, g, b, a]| Color::RGBA(r, g, b, a))
+                .collect()
+        }),
+    };
+    mesh.validate()?;
+    Ok(mesh)
 }
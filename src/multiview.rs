@@ -0,0 +1,207 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::mouse::MouseUtil;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::EventPump;
+
+use crate::camera::{Camera, Projection};
+use crate::controls::{FirstPerson, HeldKeys, Movement};
+use crate::flatshapes::draw_line;
+use crate::math::*;
+use crate::object::RenderStats;
+use crate::scene::Scene;
+use crate::target::{RenderTarget, Viewport};
+
+// Which way a view looks at the scene. Like camera space, world space has y pointing down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewKind {
+    // Orthographic, looking along +z
+    Front,
+    // Orthographic, looking down along +y with +z at the top of the screen
+    Top,
+    // Orthographic, from the right looking along -x
+    Side,
+    // Freely moving perspective camera
+    Perspective,
+}
+
+impl ViewKind {
+    // Quadrants left to right, top to bottom. The top view sits above the front view and the side
+    // view next to it, with the views lining up like on a drawing.
+    pub const LAYOUT: [ViewKind; 4] = [
+        ViewKind::Top,
+        ViewKind::Perspective,
+        ViewKind::Front,
+        ViewKind::Side,
+    ];
+    // Camera rotation looking from this side, the perspective view starts out like the front one
+    pub fn rotation(self) -> Quat {
+        match self {
+            ViewKind::Front | ViewKind::Perspective => Quat::IDENTITY,
+            ViewKind::Top => Quat::from_rotation_x(FRAC_PI_2),
+            ViewKind::Side => Quat::from_rotation_y(FRAC_PI_2),
+        }
+    }
+}
+
+pub struct View {
+    pub kind: ViewKind,
+    pub camera: Camera,
+    // Part of the window the view is drawn into
+    pub rect: Rect,
+}
+
+// The same scene seen from the front, top, side and a perspective camera, in four quadrants of
+// one window. Input goes to the camera of the view the mouse was last over, or to the
+// perspective one while it has the mouse grabbed.
+// The perspective camera looks around like a FirstPerson. The orthographic ones keep looking the
+// same way, held keys pan across them and moving forward or back zooms in or out.
+pub struct MultiView {
+    pub views: [View; 4],
+    pub active: usize,
+    pub first_person: FirstPerson,
+    // Panning and zooming of the orthographic views
+    pub movement: Movement,
+}
+
+impl MultiView {
+    // The perspective view starts out as `camera`, the others look at the origin
+    pub fn new(camera: Camera, res: (u32, u32)) -> MultiView {
+        let views = ViewKind::LAYOUT.map(|kind| View {
+            kind,
+            camera: match kind {
+                ViewKind::Perspective => camera,
+                _ => Camera::orthographic(Vec3::ZERO, kind.rotation(), 10.0, 10.0, res),
+            },
+            rect: Rect::new(0, 0, res.0, res.1),
+        });
+        let mut multi_view = MultiView {
+            views,
+            active: 1,
+            first_person: FirstPerson::new(&camera),
+            movement: Movement::new(),
+        };
+        multi_view.resize(res);
+        multi_view.frame(Vec3::splat(-5.0), Vec3::splat(5.0));
+        multi_view
+    }
+    // Splits a window of size `res` between the views
+    pub fn resize(&mut self, res: (u32, u32)) {
+        let (left, top) = (res.0 / 2, res.1 / 2);
+        for (idx, view) in self.views.iter_mut().enumerate() {
+            let (x, width) = match idx % 2 {
+                0 => (0, left),
+                _ => (left, res.0 - left),
+            };
+            let (y, height) = match idx / 2 {
+                0 => (0, top),
+                _ => (top, res.1 - top),
+            };
+            view.rect = Rect::new(x as i32, y as i32, width, height);
+            view.camera.change_res(view.rect.size());
+        }
+    }
+    // Points the orthographic views at the center of a box and zooms them to fit it all
+    pub fn frame(&mut self, min: Vec3, max: Vec3) {
+        let center = (min + max) / 2.0;
+        let radius = ((max - min).length() / 2.0).max(FP::EPSILON);
+        for view in &mut self.views {
+            if view.kind == ViewKind::Perspective {
+                continue;
+            }
+            // The whole sphere around the box has to fit across the shorter side
+            let (width, height) = view.camera.res();
+            let ratio = width.max(height) as FP / width.min(height).max(1) as FP;
+            let forward = view.camera.rot.conjugate() * Vec3::Z;
+            view.camera = Camera::orthographic(
+                center - forward * 2.0 * radius,
+                view.camera.rot,
+                2.0 * radius * ratio,
+                4.0 * radius,
+                view.camera.res(),
+            );
+        }
+    }
+    // Index of the view showing the window pixel `p`
+    pub fn view_at(&self, p: Point) -> Option<usize> {
+        self.views
+            .iter()
+            .position(|view| view.rect.contains_point(p))
+    }
+    pub fn render(&self, scene: &Scene, canvas: &mut impl RenderTarget) -> RenderStats {
+        let mut stats = RenderStats::default();
+        for (idx, view) in self.views.iter().enumerate() {
+            let mut viewport = Viewport::new(canvas, view.rect);
+            stats += scene.render_from(&mut viewport, &view.camera);
+            // Outlines double as the lines between the views
+            let color = match idx == self.active {
+                true => Color::BLUE,
+                false => Color::GRAY,
+            };
+            let (right, bottom) = (view.rect.width() as i32 - 1, view.rect.height() as i32 - 1);
+            let corners = [
+                Point::new(0, 0),
+                Point::new(right, 0),
+                Point::new(right, bottom),
+                Point::new(0, bottom),
+            ];
+            for (idx, &corner) in corners.iter().enumerate() {
+                draw_line(&mut viewport, corner, corners[(idx + 1) % 4], color);
+            }
+        }
+        stats
+    }
+    // `dt` is the time since the last call in seconds.
+    // Syncs the mouse mode with whether it is grabbed, like FirstPerson::process_inputs.
+    pub fn process_inputs(&mut self, event_pump: &mut EventPump, mouse: &MouseUtil, dt: FP) {
+        for event in event_pump.poll_iter() {
+            self.handle_event(&event);
+        }
+        let keys = HeldKeys::from_keyboard(&event_pump.keyboard_state());
+        self.update(&keys, dt);
+        if mouse.relative_mouse_mode() != self.first_person.grabbed() {
+            mouse.set_relative_mouse_mode(self.first_person.grabbed());
+        }
+    }
+    // Steers the active view's camera for the keys held over the last `dt` seconds
+    pub fn update(&mut self, keys: &HeldKeys, dt: FP) {
+        let view = &mut self.views[self.active];
+        let camera = &mut view.camera;
+        match view.kind {
+            ViewKind::Perspective => self.first_person.update(camera, keys, dt),
+            // Staying in the same plane, as the near plane goes through the camera
+            _ => {
+                let offset = self.movement.step(keys, dt);
+                camera.pos += camera.rot.conjugate() * Vec3::new(offset.x, offset.y, 0.0);
+                if let Projection::Orthographic { size, .. } = camera.projection {
+                    camera.zoom((-offset.z / size).exp());
+                }
+            }
+        }
+    }
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::MouseMotion { x, y, .. } | Event::MouseButtonDown { x, y, .. }
+                if !self.first_person.grabbed() =>
+            {
+                if let Some(idx) = self.view_at(Point::new(x, y)) {
+                    self.active = idx;
+                }
+            }
+            // The views share the window, so none of them can take its size
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(x, y),
+                ..
+            } => self.resize((x as u32, y as u32)),
+            _ => {
+                let view = &mut self.views[self.active];
+                match view.kind {
+                    ViewKind::Perspective => {
+                        self.first_person.handle_event(&mut view.camera, event)
+                    }
+                    _ => view.camera.handle_event(event),
+                }
+            }
+        }
+    }
+}
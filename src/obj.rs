@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use sdl2::pixels::Color;
+
+use crate::material::Material;
+use crate::math::*;
+use crate::meshes::{triangulate_polygon, LoadError, Mesh, Y_UP_TO_WORLD};
+use crate::object::Object;
+use crate::texture::Texture;
+
+// A run of faces sharing the same object, group and material
+pub struct ObjModel {
+    pub object: Option<String>,
+    pub group: Option<String>,
+    pub material: Option<String>,
+    pub mesh: Mesh,
+}
+
+pub struct ObjFile {
+    pub models: Vec<ObjModel>,
+    // Material libraries referenced with `mtllib`, relative to the .obj file
+    pub mtllibs: Vec<String>,
+}
+
+impl ObjFile {
+    // Reads every referenced material library, `dir` being the one containing the .obj file.
+    // Libraries that can't be loaded are reported and skipped, the models using their materials
+    // get the default one.
+    pub fn load_materials(&self, dir: &Path) -> HashMap<String, Material> {
+        let mut materials = HashMap::new();
+        for mtllib in &self.mtllibs {
+            let path = dir.join(mtllib);
+            match load_mtl(&path) {
+                Ok(loaded) => materials.extend(loaded),
+                Err(e) => eprintln!("Could not load {}: {}", path.display(), e),
+            }
+        }
+        materials
+    }
+    // Models using a material missing from `materials` get the default one
+    pub fn into_objects(self, materials: &HashMap<String, Material>) -> Vec<Object> {
+        let transform = Mat4::from_quat(Y_UP_TO_WORLD);
+        self.models
+            .into_iter()
+            .map(|model| Object {
+                material: model
+                    .material
+                    .and_then(|name| materials.get(&name).cloned())
+                    .unwrap_or_default(),
+                ..Object::from_matrix(Rc::new(model.mesh), transform)
+            })
+            .collect()
+    }
+}
+
+pub fn load_obj(path: &Path) -> Result<ObjFile, LoadError> {
+    parse_obj(BufReader::new(File::open(path)?))
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Material>, LoadError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_mtl(BufReader::new(File::open(path)?), dir)
+}
+
+// Only the diffuse color and texture are used, texture paths are relative to `dir`.
+// Textures that can't be loaded are reported and left out.
+pub fn parse_mtl(reader: impl BufRead, dir: &Path) -> Result<HashMap<String, Material>, LoadError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let err = |msg: &str| LoadError::Parse(format!("line {}: {}", line_idx + 1, msg));
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let rest: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            materials.extend(current.take());
+            current = Some((rest.join(" "), Material::default()));
+            continue;
+        }
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => return Err(err("material statement before newmtl")),
+        };
+        match keyword {
+            "Kd" => {
+                let channel = |t: &str| {
+                    t.parse::<FP>()
+                        .map(|c| (c * 255.0).round().clamp(0.0, 255.0) as u8)
+                        .map_err(|_| err("invalid number"))
+                };
+                match rest[..] {
+                    [r, g, b] => {
+                        let alpha = material.color.a;
+                        material.color = Color::RGBA(channel(r)?, channel(g)?, channel(b)?, alpha)
+                    }
+                    _ => return Err(err("Kd needs 3 values")),
+                }
+            }
+            "d" => match rest[..] {
+                [d] => {
+                    let d: FP = d.parse().map_err(|_| err("invalid number"))?;
+                    material.color.a = (d * 255.0).round().clamp(0.0, 255.0) as u8;
+                }
+                _ => return Err(err("d needs 1 value")),
+            },
+            // Options such as -blendu come before the file name, which is the last token
+            "map_Kd" => match rest.last() {
+                Some(file) => {
+                    let path = dir.join(file);
+                    match Texture::load(&path) {
+                        Ok(texture) => material.texture = Some(Arc::new(texture)),
+                        Err(e) => eprintln!("Could not load {}: {}", path.display(), e),
+                    }
+                }
+                None => return Err(err("map_Kd needs a file name")),
+            },
+            // Ambient, specular, illumination models and the other maps are ignored
+            _ => {}
+        }
+    }
+    materials.extend(current);
+    Ok(materials)
+}
+
+// Indices of a face corner into the file wide position, uv and normal lists
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Faces of the model currently being read, before being turned into a Mesh
+#[derive(Default)]
+struct ModelBuilder {
+    object: Option<String>,
+    group: Option<String>,
+    material: Option<String>,
+    faces: Vec<Vec<Corner>>,
+}
+
+pub fn parse_obj(reader: impl BufRead) -> Result<ObjFile, LoadError> {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut mtllibs = Vec::new();
+    let mut models = Vec::new();
+    let mut current = ModelBuilder::default();
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let err = |msg: &str| LoadError::Parse(format!("line {}: {}", line_idx + 1, msg));
+        // Strip comments
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let rest: Vec<&str> = tokens.collect();
+        let floats = || {
+            rest.iter()
+                .map(|t| t.parse::<FP>())
+                .collect::<Result<Vec<FP>, _>>()
+                .map_err(|_| err("invalid number"))
+        };
+        match keyword {
+            "v" => match floats()?[..] {
+                [x, y, z, ..] => positions.push(Vec3::new(x, y, z)),
+                _ => return Err(err("vertex needs 3 coordinates")),
+            },
+            "vt" => match floats()?[..] {
+                // OBJ has v going up from the bottom of the image, textures start at the top
+                [u, v, ..] => uvs.push(Vec2::new(u, 1.0 - v)),
+                [u] => uvs.push(Vec2::new(u, 1.0)),
+                _ => return Err(err("texture coordinate needs at least 1 value")),
+            },
+            "vn" => match floats()?[..] {
+                [x, y, z] => normals.push(Vec3::new(x, y, z)),
+                _ => return Err(err("normal needs 3 coordinates")),
+            },
+            "f" => {
+                if rest.len() < 3 {
+                    return Err(err("face needs at least 3 vertices"));
+                }
+                let face = rest
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(corner, positions.len(), uvs.len(), normals.len())
+                            .ok_or_else(|| err("invalid face vertex"))
+                    })
+                    .collect::<Result<Vec<Corner>, _>>()?;
+                current.faces.push(face);
+            }
+            "o" | "g" | "usemtl" => {
+                let name = Some(rest.join(" "));
+                let next = match keyword {
+                    "o" => ModelBuilder {
+                        object: name,
+                        material: current.material.clone(),
+                        ..ModelBuilder::default()
+                    },
+                    "g" => ModelBuilder {
+                        object: current.object.clone(),
+                        group: name,
+                        material: current.material.clone(),
+                        ..ModelBuilder::default()
+                    },
+                    _ => ModelBuilder {
+                        object: current.object.clone(),
+                        group: current.group.clone(),
+                        material: name,
+                        ..ModelBuilder::default()
+                    },
+                };
+                let finished = std::mem::replace(&mut current, next);
+                if !finished.faces.is_empty() {
+                    models.push(finished.build(&positions, &uvs, &normals));
+                }
+            }
+            "mtllib" => mtllibs.extend(rest.iter().map(|s| s.to_string())),
+            // Smoothing groups, lines, points and free-form geometry are ignored
+            _ => {}
+        }
+    }
+    if !current.faces.is_empty() {
+        models.push(current.build(&positions, &uvs, &normals));
+    }
+    Ok(ObjFile { models, mtllibs })
+}
+
+// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving 1-based and negative relative indices
+fn parse_corner(corner: &str, n_pos: usize, n_uv: usize, n_normal: usize) -> Option<Corner> {
+    let resolve = |idx: &str, len: usize| -> Option<usize> {
+        let idx: i64 = idx.parse().ok()?;
+        let resolved = match idx {
+            0 => return None,
+            idx if idx < 0 => len as i64 + idx,
+            idx => idx - 1,
+        };
+        (0..len as i64)
+            .contains(&resolved)
+            .then_some(resolved as usize)
+    };
+    let mut parts = corner.split('/');
+    let pos = resolve(parts.next()?, n_pos)?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        Some(idx) => Some(resolve(idx, n_uv)?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(idx) => Some(resolve(idx, n_normal)?),
+    };
+    Some((pos, uv, normal))
+}
+
+impl ModelBuilder {
+    fn build(self, positions: &[Vec3], uvs: &[Vec2], normals: &[Vec3]) -> ObjModel {
+        let has_uvs = self.faces.iter().flatten().any(|c| c.1.is_some());
+        let has_normals = self.faces.iter().flatten().any(|c| c.2.is_some());
+
+        // Every unique combination of position, uv and normal becomes one vertex
+        let mut vertex_map: HashMap<Corner, u32> = HashMap::new();
+        let mut mesh = Mesh {
+            normals: has_normals.then(Vec::new),
+            uvs: has_uvs.then(Vec::new),
+            ..Mesh::default()
+        };
+        for face in &self.faces {
+            let local: Vec<u32> = face
+                .iter()
+                .map(|corner| {
+                    *vertex_map.entry(*corner).or_insert_with(|| {
+                        mesh.vertices.push(positions[corner.0]);
+                        if let Some(mesh_uvs) = &mut mesh.uvs {
+                            mesh_uvs.push(corner.1.map_or(Vec2::ZERO, |idx| uvs[idx]));
+                        }
+                        if let Some(mesh_normals) = &mut mesh.normals {
+                            mesh_normals.push(corner.2.map_or(Vec3::ZERO, |idx| normals[idx]));
+                        }
+                        mesh.vertices.len() as u32 - 1
+                    })
+                })
+                .collect();
+            let polygon: Vec<Vec3> = face.iter().map(|corner| positions[corner.0]).collect();
+            for tri in triangulate_polygon(&polygon) {
+                mesh.indices.extend(tri.iter().map(|&idx| local[idx]));
+            }
+        }
+        ObjModel {
+            object: self.object,
+            group: self.group,
+            material: self.material,
+            mesh,
+        }
+    }
+}
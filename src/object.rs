@@ -1,51 +1,353 @@
+use std::ops::AddAssign;
 use std::rc::Rc;
 
 use sdl2::pixels::Color;
-use sdl2::render::WindowCanvas;
 
 use crate::camera::*;
+use crate::clip::*;
 use crate::flatshapes::*;
+use crate::light::*;
+use crate::material::Material;
 use crate::math::*;
+use crate::meshes::Mesh;
+use crate::pipeline::*;
 use crate::project_vertex;
+use crate::shaders::*;
+use crate::soa::SoaPoints;
+use crate::target::{DepthBuffer, RenderTarget};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+impl CullMode {
+    // Whether a triangle in clip space gets culled, given which way front faces wind
+    pub fn culls(self, winding: Winding, tri: &[Vec4; 3]) -> bool {
+        let area = signed_area(tri);
+        let front = match winding {
+            Winding::Clockwise => area > 0.0,
+            Winding::CounterClockwise => area < 0.0,
+        };
+        match self {
+            CullMode::None => false,
+            CullMode::Back => !front,
+            CullMode::Front => front,
+        }
+    }
+}
+
+// Order in which the vertices of front faces appear on screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+// Flat shading lights each face once using its normal, Gouraud lights every vertex
+// with its own normal and interpolates between them, Phong interpolates the normals
+// and lights every pixel with Blinn-Phong
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shading {
+    Flat,
+    Gouraud,
+    Phong,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, rhs: RenderStats) {
+        self.drawn += rhs.drawn;
+        self.culled += rhs.culled;
+    }
+}
 
 pub struct Object {
-    pub pos: Vec3,
-    pub rot: Quat,
-    pub scale: FP,
-    pub mesh: Rc<Vec<Vec3>>,
+    // Object space to world space
+    pub transform: Affine3,
+    pub mesh: Rc<Mesh>,
+    pub cull: CullMode,
+    pub winding: Winding,
+    // Width in pixels of the squares drawn for point clouds
+    pub point_size: u32,
+    pub material: Material,
+    pub shading: Shading,
 }
 
 impl Object {
-    pub fn render(&self, canvas: &mut WindowCanvas, camera: &Camera) {
-        assert_eq!(self.mesh.len() % 3, 0);
-        for idx in (0..self.mesh.len()).step_by(3) {
-            let v_a = project_vertex(
-                camera,
-                &((self.rot * self.mesh[idx] * self.scale) + self.pos),
-            );
-            let v_b = project_vertex(
-                camera,
-                &((self.rot * self.mesh[idx + 1] * self.scale) + self.pos),
-            );
-            let v_c = project_vertex(
+    pub fn new(mesh: Rc<Mesh>) -> Object {
+        Object {
+            transform: Affine3::IDENTITY,
+            mesh,
+            cull: CullMode::None,
+            winding: Winding::CounterClockwise,
+            point_size: 2,
+            material: Material::default(),
+            shading: Shading::Flat,
+        }
+    }
+    // The matrix has to be affine
+    pub fn from_matrix(mesh: Rc<Mesh>, transform: Mat4) -> Object {
+        let transform = Affine3::from_mat4(transform);
+        // A mirroring transform flips which way the faces wind
+        let winding = match transform.matrix3.determinant() < 0.0 {
+            true => Winding::Clockwise,
+            false => Winding::CounterClockwise,
+        };
+        Object {
+            transform,
+            winding,
+            ..Object::new(mesh)
+        }
+    }
+    // Smallest and largest world space coordinates of the vertices, None without any
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut points = self
+            .mesh
+            .vertices
+            .iter()
+            .map(|&v| transform_point(&self.transform, v));
+        let first = points.next()?;
+        Some(points.fold((first, first), |(min, max), p| (min.min(p), max.max(p))))
+    }
+    pub fn render(&self, canvas: &mut impl RenderTarget, camera: &Camera) -> RenderStats {
+        if self.mesh.is_point_cloud() {
+            return self.render_points(canvas, None, camera);
+        }
+        let (_, clip) = self.transform_vertices(camera);
+        let (polygons, stats) = clip_triangles(
+            self.cull,
+            self.winding,
+            &clip,
+            self.mesh.triangles(),
+            |_, tri| clip_polygon_edges(tri, ClipPlane::FRUSTUM),
+        );
+        for polygon in polygons {
+            // Edges the clipping added along the sides of the view aren't part of the mesh
+            for (idx, &(v, original)) in polygon.iter().enumerate() {
+                if !original {
+                    continue;
+                }
+                let next = polygon[(idx + 1) % polygon.len()].0;
+                draw_line(
+                    canvas,
+                    clip_to_screen(camera, v).0,
+                    clip_to_screen(camera, next).0,
+                    Color::BLACK,
+                );
+            }
+        }
+        stats
+    }
+    // Without any lights a headlight is used, so that the faces can still be told apart
+    pub fn render_solid(
+        &self,
+        canvas: &mut impl RenderTarget,
+        depth: &mut DepthBuffer,
+        camera: &Camera,
+        lights: &[Light],
+    ) -> RenderStats {
+        if self.mesh.is_point_cloud() {
+            return self.render_points(canvas, Some(depth), camera);
+        }
+        let headlight = headlight(camera.pos);
+        let lights = match lights.is_empty() {
+            true => &headlight[..],
+            false => lights,
+        };
+        let mut sink = Immediate {
+            canvas,
+            depth: Some(depth),
+        };
+        self.submit_solid(&mut sink, camera, lights)
+    }
+    // Hands the triangles of render_solid to `sink` instead of drawing them.
+    // Point clouds are left out, and `lights` is used as is.
+    pub fn submit_solid<'a>(
+        &'a self,
+        sink: &mut impl TriangleSink<'a>,
+        camera: &Camera,
+        lights: &'a [Light],
+    ) -> RenderStats {
+        if self.mesh.is_point_cloud() {
+            return RenderStats::default();
+        }
+        let light = Diffuse {
+            lights,
+            amount: self.material.diffuse,
+            eye: camera.pos,
+            two_sided: self.cull == CullMode::None,
+        };
+        let color = self.material.color;
+        match (self.shading, &self.material.texture, &self.mesh.uvs) {
+            (Shading::Phong, _, _) => self.submit_phong(sink, camera, lights),
+            (_, Some(texture), Some(_)) => self.submit_diffuse(
+                sink,
                 camera,
-                &((self.rot * self.mesh[idx + 2] * self.scale) + self.pos),
-            );
-            if v_a.is_none() || v_b.is_none() || v_c.is_none() {
+                light,
+                textured_shader(texture, self.material.filter, color),
+            ),
+            _ => self.submit_diffuse(sink, camera, light, untextured_shader(color)),
+        }
+    }
+    // Flat or Gouraud shading, `fragment` gets the texture coordinates and light intensity
+    fn submit_diffuse<'a>(
+        &'a self,
+        sink: &mut impl TriangleSink<'a>,
+        camera: &Camera,
+        light: Diffuse<'a>,
+        fragment: impl FragmentShader<(Vec2, FP)> + Sync + 'a,
+    ) -> RenderStats {
+        match self.shading {
+            Shading::Flat => {
+                let (world, clip) = self.transform_vertices(camera);
+                let uv = |idx: usize| self.mesh.uvs.as_ref().map_or(Vec2::ZERO, |uvs| uvs[idx]);
+                // Each face left after culling gets lit once at its center, and its corners
+                // carry that light along with their texture coordinates
+                let (polygons, stats) = clip_triangles(
+                    self.cull,
+                    self.winding,
+                    &clip,
+                    self.mesh.triangles(),
+                    |[a, b, c], _| {
+                        let center = (world[a] + world[b] + world[c]) / 3.0;
+                        let normal = self.face_normal(world[a], world[b], world[c]);
+                        let h = light.intensity(center, normal);
+                        let corners = [a, b, c].map(|idx| (clip[idx], (uv(idx), h)));
+                        clip_polygon(&corners, ClipPlane::FRUSTUM)
+                    },
+                );
+                sink.submit(fragment, polygons_to_screen(camera, &polygons));
+                stats
+            }
+            _ => {
+                let vertex = GouraudShader {
+                    model: ModelShader::new(self.transform, camera),
+                    light,
+                };
+                self.pipeline(vertex, fragment).submit(
+                    sink,
+                    camera,
+                    &self.surfaces(),
+                    self.mesh.triangles(),
+                )
+            }
+        }
+    }
+    fn submit_phong<'a>(
+        &'a self,
+        sink: &mut impl TriangleSink<'a>,
+        camera: &Camera,
+        lights: &'a [Light],
+    ) -> RenderStats {
+        let vertex = ModelShader::new(self.transform, camera);
+        let fragment = BlinnPhongShader {
+            material: &self.material,
+            texture: self.mesh.uvs.as_ref().and(self.material.texture.as_deref()),
+            lights,
+            eye: camera.pos,
+            two_sided: self.cull == CullMode::None,
+        };
+        self.pipeline(vertex, fragment).submit(
+            sink,
+            camera,
+            &self.surfaces(),
+            self.mesh.triangles(),
+        )
+    }
+    // Pipeline culling the way this object does
+    fn pipeline<VS: VertexShader, FS: FragmentShader<VS::Varying>>(
+        &self,
+        vertex: VS,
+        fragment: FS,
+    ) -> Pipeline<VS, FS> {
+        Pipeline {
+            cull: self.cull,
+            winding: self.winding,
+            ..Pipeline::new(vertex, fragment)
+        }
+    }
+    // Object space positions, normals and texture coordinates of the vertices
+    fn surfaces(&self) -> Vec<Surface> {
+        let uvs = self.mesh.uvs.as_ref();
+        self.mesh
+            .vertices
+            .iter()
+            .zip(self.normals())
+            .enumerate()
+            .map(|(idx, (pos, normal))| Surface {
+                pos: *pos,
+                normal,
+                uv: uvs.map_or(Vec2::ZERO, |uvs| uvs[idx]),
+            })
+            .collect()
+    }
+    fn render_points(
+        &self,
+        canvas: &mut impl RenderTarget,
+        mut depth: Option<&mut DepthBuffer>,
+        camera: &Camera,
+    ) -> RenderStats {
+        let (_, clip) = self.transform_vertices(camera);
+        let mut stats = RenderStats::default();
+        for (idx, v) in clip.into_iter().enumerate() {
+            if ClipPlane::FRUSTUM
+                .iter()
+                .any(|plane| plane.distance(v) < 0.0)
+            {
                 continue;
             }
-            draw_triangle(
-                canvas,
-                v_a.unwrap(),
-                v_b.unwrap(),
-                v_c.unwrap(),
-                Color::BLACK,
-            );
+            let color = match &self.mesh.colors {
+                Some(colors) => colors[idx],
+                None => Color::BLACK,
+            };
+            let (p, z) = clip_to_screen(camera, v);
+            draw_splat(canvas, depth.as_deref_mut(), p, z, self.point_size, color);
+            stats.drawn += 1;
         }
+        stats
+    }
+    // Outward normal of a face given in world space
+    fn face_normal(&self, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        match self.winding {
+            Winding::Clockwise => -normal,
+            Winding::CounterClockwise => normal,
+        }
+    }
+    // Object space normals from the mesh if it has them, otherwise smoothed from the faces.
+    // Those only need flipping if the mesh itself winds the other way. A mirroring transform
+    // flips `winding` as well, but transform_normal already takes care of that.
+    fn normals(&self) -> Vec<Vec3> {
+        match &self.mesh.normals {
+            Some(normals) => normals.clone(),
+            None => {
+                let mirrored = self.transform.matrix3.determinant() < 0.0;
+                let flip = match (self.winding == Winding::Clockwise) != mirrored {
+                    true => -1.0,
+                    false => 1.0,
+                };
+                let normals = self.mesh.computed_normals();
+                normals.iter().map(|n| *n * flip).collect()
+            }
+        }
+    }
+    // Transform every vertex once up front, as shared vertices are used by several triangles.
+    // Returns world space and clip space positions.
+    fn transform_vertices(&self, camera: &Camera) -> (Vec<Vec3>, Vec<Vec4>) {
+        let world = SoaPoints::new(&self.mesh.vertices).transform(&Mat4::from(self.transform));
+        (world.to_points(), world.to_clip(camera))
     }
 }
 
-pub fn draw_cube(canvas: &mut WindowCanvas, scale: i32, camera: &Camera) {
+pub fn draw_cube(canvas: &mut impl RenderTarget, scale: i32, camera: &Camera) {
     let scale = scale as FP;
     let fv_a = Vec3::new(-scale, -scale, 0.0);
     let fv_b = Vec3::new(scale, -scale, 0.0);
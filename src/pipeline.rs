@@ -0,0 +1,404 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+
+use crate::camera::Camera;
+use crate::clip::*;
+use crate::flatshapes::put_color;
+use crate::math::*;
+use crate::object::{CullMode, RenderStats, Winding};
+use crate::target::{DepthBuffer, RenderTarget};
+
+// Turns one input vertex into a clip space position (see clip.rs) and the varyings
+// that get interpolated across triangles for the fragment shader
+pub trait VertexShader {
+    type Input;
+    type Varying: Lerp;
+    fn shade(&self, input: &Self::Input) -> (Vec4, Self::Varying);
+}
+
+// Colors one pixel, returning None discards it without touching the depth buffer
+pub trait FragmentShader<V> {
+    fn shade(&self, fragment: &Fragment<V>) -> Option<Color>;
+}
+
+impl<V, F: Fn(&Fragment<V>) -> Option<Color>> FragmentShader<V> for F {
+    fn shade(&self, fragment: &Fragment<V>) -> Option<Color> {
+        self(fragment)
+    }
+}
+
+// Screen point with the depth to test (see clip_to_screen), 1/w of its clip space position
+// and any varyings.
+// Positions are continuous, pixel (x, y) spans x..x + 1 and y..y + 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Xyzv<V> {
+    pub x: FP,
+    pub y: FP,
+    pub z: FP,
+    pub w: FP,
+    pub v: V,
+}
+
+impl<V> Xyzv<V> {
+    // With 1/w the same as the depth, as for a perspective projection without a far plane.
+    // Equal depths for all vertices interpolate linearly in screen space.
+    pub fn new(x: FP, y: FP, z: FP, v: V) -> Xyzv<V> {
+        Xyzv { x, y, z, w: z, v }
+    }
+    // Vertex at the corner of a whole pixel
+    pub fn at_pixel(p: Point, z: FP, v: V) -> Xyzv<V> {
+        Xyzv::new(p.x as FP, p.y as FP, z, v)
+    }
+    pub fn map<U>(self, f: impl FnOnce(V) -> U) -> Xyzv<U> {
+        Xyzv {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            w: self.w,
+            v: f(self.v),
+        }
+    }
+    // Where a clip space position ends up on screen, it must have been clipped against the near plane
+    pub fn from_clip(camera: &Camera, v: Vec4, varying: V) -> Xyzv<V> {
+        let (p, z) = clip_to_viewport(camera, v);
+        Xyzv {
+            x: p.x,
+            y: p.y,
+            z,
+            w: v.w.recip(),
+            v: varying,
+        }
+    }
+}
+
+// Vertex positions are snapped to 1/16th of a pixel
+const SUBPIXEL_BITS: i32 = 4;
+const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+
+fn to_fixed(x: FP, y: FP) -> IVec2 {
+    let scale = SUBPIXEL as FP;
+    IVec2::new((x * scale).round() as i32, (y * scale).round() as i32)
+}
+
+// Edge function of a -> b at p, positive on the inside of triangles that are clockwise on
+// screen. In fixed point squared, so i64 to have room for large coordinates.
+fn edge(a: IVec2, b: IVec2, p: IVec2) -> i64 {
+    (b.x - a.x) as i64 * (p.y - a.y) as i64 - (b.y - a.y) as i64 * (p.x - a.x) as i64
+}
+
+// Pixels whose center is exactly on an edge only belong to the triangle if that edge
+// is a top edge (horizontal, above the rest) or a left edge (going up for clockwise triangles).
+// Triangles sharing an edge see it going opposite ways, so exactly one of them draws the pixel.
+fn is_top_left(a: IVec2, b: IVec2) -> bool {
+    let d = b - a;
+    (d.y == 0 && d.x > 0) || d.y < 0
+}
+
+// Perspective correct interpolation from barycentric coordinates, which are linear in screen
+// space. Weighting them by 1/w and renormalizing gives the ones in camera space.
+// Depth is linear in screen space and doesn't need that.
+struct Interpolator<V> {
+    z: [FP; 3],
+    w: [FP; 3],
+    v: [V; 3],
+    // Change of the barycentric coordinates from one pixel to the next along x and y
+    step_x: [FP; 3],
+    step_y: [FP; 3],
+}
+
+impl<V: Lerp> Interpolator<V> {
+    // Depth and varyings for the given screen space barycentric coordinates
+    fn at(&self, b: [FP; 3]) -> (FP, V) {
+        let z = b[0] * self.z[0] + b[1] * self.z[1] + b[2] * self.z[2];
+        let w = [b[0] * self.w[0], b[1] * self.w[1], b[2] * self.w[2]];
+        let sum = w[0] + w[1] + w[2];
+        let (w0, w1, w2) = (w[0] / sum, w[1] / sum, w[2] / sum);
+        // Only lerps are available, so blend 0 and 1 first and then that with 2
+        let v = match w0 + w1 {
+            w01 if w01.abs() < 1e-12 => self.v[2],
+            w01 => self.v[0].lerp(self.v[1], w1 / w01).lerp(self.v[2], w2),
+        };
+        (z, v)
+    }
+}
+
+// A pixel about to be shaded
+pub struct Fragment<'a, V> {
+    pub p: Point,
+    pub z: FP,
+    pub varying: V,
+    barycentric: [FP; 3],
+    interpolator: &'a Interpolator<V>,
+}
+
+impl<V: Lerp> Fragment<'_, V> {
+    // Varyings one pixel to the right and one pixel down, for screen space derivatives
+    pub fn right(&self) -> V {
+        let (b, step) = (self.barycentric, self.interpolator.step_x);
+        self.interpolator
+            .at([b[0] + step[0], b[1] + step[1], b[2] + step[2]])
+            .1
+    }
+    pub fn below(&self) -> V {
+        let (b, step) = (self.barycentric, self.interpolator.step_y);
+        self.interpolator
+            .at([b[0] + step[0], b[1] + step[1], b[2] + step[2]])
+            .1
+    }
+}
+
+// The one rasterizer for filled triangles, everything else feeds it varyings.
+// Pixels are covered when their center is inside the triangle, going over its bounding box
+// with edge functions in fixed point. Either winding is drawn.
+// Pass the same z for every vertex to interpolate linearly in screen space instead.
+pub fn draw_varying_triangle<V: Lerp>(
+    canvas: &mut impl RenderTarget,
+    depth: Option<&mut DepthBuffer>,
+    p0: Xyzv<V>,
+    p1: Xyzv<V>,
+    p2: Xyzv<V>,
+    fragment: &impl FragmentShader<V>,
+) {
+    draw_varying_triangle_at(canvas, depth, Point::new(0, 0), p0, p1, p2, fragment);
+}
+
+// Same as draw_varying_triangle with the canvas and depth buffer only covering part of the
+// screen, starting at `origin`. Fragments still get screen coordinates.
+// Whether a pixel is covered doesn't depend on the part, so parts can be drawn separately.
+pub fn draw_varying_triangle_at<V: Lerp>(
+    canvas: &mut impl RenderTarget,
+    mut depth: Option<&mut DepthBuffer>,
+    origin: Point,
+    p0: Xyzv<V>,
+    p1: Xyzv<V>,
+    p2: Xyzv<V>,
+    fragment: &impl FragmentShader<V>,
+) {
+    let (f0, f1, f2) = (
+        to_fixed(p0.x, p0.y),
+        to_fixed(p1.x, p1.y),
+        to_fixed(p2.x, p2.y),
+    );
+    let area = edge(f0, f1, f2);
+    // Make the triangle clockwise so that the inside is where every edge function is positive
+    let (f1, f2, p1, p2, area) = match area {
+        0 => return,
+        area if area < 0 => (f2, f1, p2, p1, -area),
+        area => (f1, f2, p1, p2, area),
+    };
+
+    // Pixels with their center inside the bounding box, clamped to the canvas
+    let (width, height) = canvas.size();
+    let min = f0.min(f1).min(f2);
+    let max = f0.max(f1).max(f2);
+    let x_start = (min.x >> SUBPIXEL_BITS).max(origin.x);
+    let y_start = (min.y >> SUBPIXEL_BITS).max(origin.y);
+    let x_end = ((max.x >> SUBPIXEL_BITS) + 1).min(origin.x + width as i32);
+    let y_end = ((max.y >> SUBPIXEL_BITS) + 1).min(origin.y + height as i32);
+    if x_start >= x_end || y_start >= y_end {
+        return;
+    }
+
+    // Edge functions opposite of each vertex, which are its barycentric coordinate times the area
+    let edges = [(f1, f2), (f2, f0), (f0, f1)];
+    let bias = edges.map(|(a, b)| match is_top_left(a, b) {
+        true => 0,
+        false => -1,
+    });
+    let center = |x: i32, y: i32| {
+        let half = SUBPIXEL as i32 / 2;
+        IVec2::new((x << SUBPIXEL_BITS) + half, (y << SUBPIXEL_BITS) + half)
+    };
+    let start = center(x_start, y_start);
+    let mut row = edges.map(|(a, b)| edge(a, b, start));
+    let step_x = edges.map(|(a, b)| -(b.y - a.y) as i64 * SUBPIXEL);
+    let step_y = edges.map(|(a, b)| (b.x - a.x) as i64 * SUBPIXEL);
+
+    let area_fp = area as FP;
+    let interpolator = Interpolator {
+        z: [p0.z, p1.z, p2.z],
+        w: [p0.w, p1.w, p2.w],
+        v: [p0.v, p1.v, p2.v],
+        step_x: step_x.map(|s| s as FP / area_fp),
+        step_y: step_y.map(|s| s as FP / area_fp),
+    };
+
+    for y in y_start..y_end {
+        let mut w = row;
+        for x in x_start..x_end {
+            if w[0] + bias[0] >= 0 && w[1] + bias[1] >= 0 && w[2] + bias[2] >= 0 {
+                let p = Point::new(x, y);
+                let local = p - origin;
+                let barycentric = w.map(|w| w as FP / area_fp);
+                let (z, varying) = interpolator.at(barycentric);
+                let visible = match depth.as_deref() {
+                    Some(depth) => depth.test(local, z),
+                    None => true,
+                };
+                let color = match visible {
+                    true => fragment.shade(&Fragment {
+                        p,
+                        z,
+                        varying,
+                        barycentric,
+                        interpolator: &interpolator,
+                    }),
+                    false => None,
+                };
+                if let Some(color) = color {
+                    if let Some(depth) = depth.as_deref_mut() {
+                        depth.test_and_set(local, z);
+                    }
+                    put_color(canvas, local, color);
+                }
+            }
+            for idx in 0..3 {
+                w[idx] += step_x[idx];
+            }
+        }
+        for idx in 0..3 {
+            row[idx] += step_y[idx];
+        }
+    }
+}
+
+// Where screen space triangles go once they are ready to be rasterized, along with the fragment
+// shader for all of them. Anything submitted has to stay around for 'a, and be shareable
+// between threads, as it might only get drawn later on.
+pub trait TriangleSink<'a> {
+    fn submit<V, FS>(&mut self, fragment: FS, triangles: Vec<[Xyzv<V>; 3]>)
+    where
+        V: Lerp + Send + Sync + 'a,
+        FS: FragmentShader<V> + Sync + 'a;
+}
+
+// Rasterizes everything right away
+pub struct Immediate<'c, T> {
+    pub canvas: &'c mut T,
+    pub depth: Option<&'c mut DepthBuffer>,
+}
+
+impl<'a, T: RenderTarget> TriangleSink<'a> for Immediate<'_, T> {
+    fn submit<V, FS>(&mut self, fragment: FS, triangles: Vec<[Xyzv<V>; 3]>)
+    where
+        V: Lerp + Send + Sync + 'a,
+        FS: FragmentShader<V> + Sync + 'a,
+    {
+        for [p0, p1, p2] in triangles {
+            draw_varying_triangle(
+                self.canvas,
+                self.depth.as_deref_mut(),
+                p0,
+                p1,
+                p2,
+                &fragment,
+            );
+        }
+    }
+}
+
+// Runs vertices through the vertex shader, then culls, clips and rasterizes the triangles
+pub struct Pipeline<VS, FS> {
+    pub vertex: VS,
+    pub fragment: FS,
+    pub cull: CullMode,
+    pub winding: Winding,
+}
+
+impl<VS: VertexShader, FS: FragmentShader<VS::Varying>> Pipeline<VS, FS> {
+    pub fn new(vertex: VS, fragment: FS) -> Pipeline<VS, FS> {
+        Pipeline {
+            vertex,
+            fragment,
+            cull: CullMode::None,
+            winding: Winding::CounterClockwise,
+        }
+    }
+    pub fn draw(
+        &self,
+        canvas: &mut impl RenderTarget,
+        mut depth: Option<&mut DepthBuffer>,
+        camera: &Camera,
+        inputs: &[VS::Input],
+        triangles: impl Iterator<Item = [usize; 3]>,
+    ) -> RenderStats {
+        let (screen, stats) = self.project(camera, inputs, triangles);
+        for [p0, p1, p2] in screen {
+            draw_varying_triangle(canvas, depth.as_deref_mut(), p0, p1, p2, &self.fragment);
+        }
+        stats
+    }
+    // Like draw, handing the triangles and the fragment shader over to `sink`
+    pub fn submit<'a>(
+        self,
+        sink: &mut impl TriangleSink<'a>,
+        camera: &Camera,
+        inputs: &[VS::Input],
+        triangles: impl Iterator<Item = [usize; 3]>,
+    ) -> RenderStats
+    where
+        VS::Varying: Send + Sync + 'a,
+        FS: Sync + 'a,
+    {
+        let (screen, stats) = self.project(camera, inputs, triangles);
+        sink.submit(self.fragment, screen);
+        stats
+    }
+    // Screen space triangles that are left after culling and clipping
+    fn project(
+        &self,
+        camera: &Camera,
+        inputs: &[VS::Input],
+        triangles: impl Iterator<Item = [usize; 3]>,
+    ) -> (Vec<[Xyzv<VS::Varying>; 3]>, RenderStats) {
+        // Shared vertices are only shaded once
+        let shaded: Vec<(Vec4, VS::Varying)> =
+            inputs.iter().map(|i| self.vertex.shade(i)).collect();
+        let (polygons, stats) =
+            clip_triangles(self.cull, self.winding, &shaded, triangles, |_, tri| {
+                clip_polygon(tri, ClipPlane::FRUSTUM)
+            });
+        (polygons_to_screen(camera, &polygons), stats)
+    }
+}
+
+// Splits clipped polygons into screen space triangles
+pub fn polygons_to_screen<V: Copy>(
+    camera: &Camera,
+    polygons: &[Vec<(Vec4, V)>],
+) -> Vec<[Xyzv<V>; 3]> {
+    let to_screen = |(v, varying)| Xyzv::from_clip(camera, v, varying);
+    polygons
+        .iter()
+        .flat_map(|polygon| triangulate_fan(polygon).map(|tri| tri.map(to_screen)))
+        .collect()
+}
+
+// Culls triangles of clip space vertices and clips the others against the view frustum with
+// `clip`, which gets the indices of the triangle along with its vertices and calls either
+// clip_polygon or clip_polygon_edges, returning what is left of each one. Triangles that end
+// up entirely outside of the view count as neither drawn nor culled.
+pub fn clip_triangles<T: ClipVertex, U>(
+    cull: CullMode,
+    winding: Winding,
+    vertices: &[T],
+    triangles: impl Iterator<Item = [usize; 3]>,
+    mut clip: impl FnMut([usize; 3], &[T; 3]) -> Vec<U>,
+) -> (Vec<Vec<U>>, RenderStats) {
+    let mut polygons = Vec::new();
+    let mut stats = RenderStats::default();
+    for [a, b, c] in triangles {
+        let tri = [vertices[a], vertices[b], vertices[c]];
+        if cull.culls(winding, &tri.map(|v| v.position())) {
+            stats.culled += 1;
+            continue;
+        }
+        let polygon = clip([a, b, c], &tri);
+        if polygon.len() < 3 {
+            continue;
+        }
+        stats.drawn += 1;
+        polygons.push(polygon);
+    }
+    (polygons, stats)
+}
@@ -0,0 +1,310 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::str::SplitWhitespace;
+
+use sdl2::pixels::Color;
+
+use crate::math::*;
+use crate::meshes::{triangulate_polygon, LoadError, Mesh};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    // Name, type of the length and type of the items
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+enum Value {
+    Scalar(FP),
+    List(Vec<FP>),
+}
+
+// Reads values out of the body of the file, in whichever format it is
+enum Body<'a> {
+    Ascii(SplitWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<FP, LoadError> {
+        let eof = || LoadError::Parse("unexpected end of file".to_string());
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or_else(eof)?;
+                token
+                    .parse()
+                    .map_err(|_| LoadError::Parse(format!("invalid number {}", token)))
+            }
+            Body::Binary {
+                data,
+                pos,
+                big_endian,
+            } => {
+                let bytes = data.get(*pos..*pos + scalar.size()).ok_or_else(eof)?;
+                *pos += scalar.size();
+                macro_rules! read {
+                    ($t:ty) => {{
+                        let bytes = bytes.try_into().unwrap();
+                        match big_endian {
+                            true => <$t>::from_be_bytes(bytes) as FP,
+                            false => <$t>::from_le_bytes(bytes) as FP,
+                        }
+                    }};
+                }
+                Ok(match scalar {
+                    Scalar::I8 => read!(i8),
+                    Scalar::U8 => read!(u8),
+                    Scalar::I16 => read!(i16),
+                    Scalar::U16 => read!(u16),
+                    Scalar::I32 => read!(i32),
+                    Scalar::U32 => read!(u32),
+                    Scalar::F32 => read!(f32),
+                    Scalar::F64 => read!(f64),
+                })
+            }
+        }
+    }
+}
+
+pub fn load_ply(path: &Path) -> Result<Mesh, LoadError> {
+    parse_ply(&fs::read(path)?)
+}
+
+// List values are read as floats, only whole numbers that fit a u32 are valid indices
+fn face_index(i: FP) -> Result<u32, LoadError> {
+    match i >= 0.0 && i <= u32::MAX as FP && i.fract() == 0.0 {
+        true => Ok(i as u32),
+        false => Err(LoadError::Parse(format!("invalid face index {}", i))),
+    }
+}
+
+// Files without faces are returned as a point cloud, a mesh without any indices.
+// Vertex colors and normals are read when present.
+pub fn parse_ply(data: &[u8]) -> Result<Mesh, LoadError> {
+    let (format, elements, body_start) = parse_header(data)?;
+    let mut body = match format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(&data[body_start..])
+                .map_err(|_| LoadError::Parse("ASCII PLY is not valid UTF-8".to_string()))?
+                .split_whitespace(),
+        ),
+        _ => Body::Binary {
+            data,
+            pos: body_start,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut mesh = Mesh::default();
+    let mut faces: Vec<Vec<u32>> = Vec::new();
+    for element in &elements {
+        let find = |name: &str| {
+            element.properties.iter().position(|p| match p {
+                Property::Scalar(p_name, _) | Property::List(p_name, _, _) => p_name == name,
+            })
+        };
+        let position = [find("x"), find("y"), find("z")];
+        let normal = [find("nx"), find("ny"), find("nz")];
+        let color = [find("red"), find("green"), find("blue")];
+        let alpha = find("alpha");
+        let indices = find("vertex_indices").or_else(|| find("vertex_index"));
+        // Integer colors are 0-255, floating point ones are 0-1
+        let color_scale = match color[0].map(|idx| &element.properties[idx]) {
+            Some(Property::Scalar(_, Scalar::F32)) | Some(Property::Scalar(_, Scalar::F64)) => {
+                255.0
+            }
+            _ => 1.0,
+        };
+
+        for _ in 0..element.count {
+            let record = element
+                .properties
+                .iter()
+                .map(|p| match p {
+                    Property::Scalar(_, scalar) => Ok(Value::Scalar(body.read(*scalar)?)),
+                    Property::List(_, len, item) => {
+                        let len = body.read(*len)? as usize;
+                        (0..len)
+                            .map(|_| body.read(*item))
+                            .collect::<Result<_, _>>()
+                            .map(Value::List)
+                    }
+                })
+                .collect::<Result<Vec<Value>, LoadError>>()?;
+            let scalar = |idx: Option<usize>| match idx.map(|idx| &record[idx]) {
+                Some(Value::Scalar(v)) => Some(*v),
+                _ => None,
+            };
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let [x, y, z] = position.map(scalar);
+                    mesh.vertices.push(Vec3::new(
+                        x.ok_or(LoadError::MissingAttribute("x"))?,
+                        y.ok_or(LoadError::MissingAttribute("y"))?,
+                        z.ok_or(LoadError::MissingAttribute("z"))?,
+                    ));
+                    if let [Some(x), Some(y), Some(z)] = normal.map(scalar) {
+                        mesh.normals
+                            .get_or_insert_with(Vec::new)
+                            .push(Vec3::new(x, y, z));
+                    }
+                    if let [Some(r), Some(g), Some(b)] = color.map(scalar) {
+                        let channel = |c: FP| (c * color_scale).round().clamp(0.0, 255.0) as u8;
+                        let a = scalar(alpha).map_or(255, channel);
+                        mesh.colors.get_or_insert_with(Vec::new).push(Color::RGBA(
+                            channel(r),
+                            channel(g),
+                            channel(b),
+                            a,
+                        ));
+                    }
+                }
+                "face" => match indices.map(|idx| &record[idx]) {
+                    Some(Value::List(list)) => {
+                        let face = list
+                            .iter()
+                            .map(|&i| face_index(i))
+                            .collect::<Result<_, _>>()?;
+                        faces.push(face);
+                    }
+                    _ => return Err(LoadError::MissingAttribute("vertex_indices")),
+                },
+                // Edges, materials and anything else are skipped
+                _ => {}
+            }
+        }
+    }
+
+    for face in faces {
+        if let Some(&idx) = face
+            .iter()
+            .find(|&&idx| idx as usize >= mesh.vertices.len())
+        {
+            return Err(LoadError::Parse(format!("face index {} out of range", idx)));
+        }
+        let polygon: Vec<Vec3> = face
+            .iter()
+            .map(|&idx| mesh.vertices[idx as usize])
+            .collect();
+        for tri in triangulate_polygon(&polygon) {
+            mesh.indices.extend(tri.iter().map(|&idx| face[idx]));
+        }
+    }
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+// Returns the format, the declared elements and where the body starts
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), LoadError> {
+    let err = |msg: &str| LoadError::Parse(msg.to_string());
+    if !data.starts_with(b"ply") {
+        return Err(err("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    loop {
+        let line_end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| err("missing end_header"))?;
+        let line = std::str::from_utf8(&data[pos..pos + line_end])
+            .map_err(|_| err("header is not valid UTF-8"))?
+            .trim();
+        pos += line_end + 1;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["ply"] => {}
+            ["format", name, _version] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(err("unknown format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| err("invalid element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", len, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| err("property before element"))?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(len).ok_or_else(|| err("unknown property type"))?,
+                    Scalar::parse(item).ok_or_else(|| err("unknown property type"))?,
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| err("property before element"))?
+                .properties
+                .push(Property::Scalar(
+                    name.to_string(),
+                    Scalar::parse(scalar).ok_or_else(|| err("unknown property type"))?,
+                )),
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(LoadError::Parse(format!("invalid header line {}", line))),
+        }
+    }
+    let format = format.ok_or_else(|| err("missing format"))?;
+    Ok((format, elements, pos))
+}
@@ -0,0 +1,66 @@
+use crate::camera::*;
+use crate::light::{headlight, Light};
+use crate::math::Vec3;
+use crate::object::*;
+use crate::target::{DepthBuffer, Framebuffer, RenderTarget};
+use crate::tiles::Bins;
+
+pub struct Scene {
+    pub camera: Camera,
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    // Threads rasterizing tiles in render_tiled, 0 uses one per core
+    pub threads: usize,
+}
+
+impl Scene {
+    // Box around all objects, None if there is nothing to show
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.objects
+            .iter()
+            .filter_map(Object::bounds)
+            .reduce(|(min, max), (obj_min, obj_max)| (min.min(obj_min), max.max(obj_max)))
+    }
+    pub fn render(&self, canvas: &mut impl RenderTarget) -> RenderStats {
+        self.render_from(canvas, &self.camera)
+    }
+    // Wireframe as seen by any camera, not just the scene's own
+    pub fn render_from(&self, canvas: &mut impl RenderTarget, camera: &Camera) -> RenderStats {
+        let mut stats = RenderStats::default();
+        for obj in &self.objects {
+            stats += obj.render(canvas, camera);
+        }
+        stats
+    }
+    pub fn render_solid(
+        &self,
+        canvas: &mut impl RenderTarget,
+        depth: &mut DepthBuffer,
+    ) -> RenderStats {
+        let mut stats = RenderStats::default();
+        for obj in &self.objects {
+            stats += obj.render_solid(canvas, depth, &self.camera, &self.lights);
+        }
+        stats
+    }
+    // Same picture as render_solid, with the screen split into tiles drawn in parallel
+    pub fn render_tiled(&self, canvas: &mut Framebuffer, depth: &mut DepthBuffer) -> RenderStats {
+        let headlight = headlight(self.camera.pos);
+        let lights = match self.lights.is_empty() {
+            true => &headlight[..],
+            false => &self.lights[..],
+        };
+        let (width, height) = canvas.size();
+        let mut bins = Bins::new(width, height);
+        let mut stats = RenderStats::default();
+        for obj in &self.objects {
+            stats += obj.submit_solid(&mut bins, &self.camera, lights);
+        }
+        bins.rasterize(canvas, depth, self.threads);
+        // Points are quick enough to draw directly, they are depth tested all the same
+        for obj in self.objects.iter().filter(|obj| obj.mesh.is_point_cloud()) {
+            stats += obj.render_solid(canvas, depth, &self.camera, lights);
+        }
+        stats
+    }
+}
@@ -0,0 +1,125 @@
+use sdl2::pixels::Color;
+
+use crate::camera::Camera;
+use crate::light::*;
+use crate::material::Material;
+use crate::math::*;
+use crate::pipeline::*;
+use crate::texture::*;
+
+// Places object space surfaces in the world, as done for every Object
+pub struct ModelShader {
+    pub model: Affine3,
+    // Inverse of the model transform, for the normals
+    pub inverse: Affine3,
+    pub view_projection: Mat4,
+}
+
+impl ModelShader {
+    pub fn new(model: Affine3, camera: &Camera) -> ModelShader {
+        ModelShader {
+            model,
+            inverse: model.inverse(),
+            view_projection: camera.view_projection(),
+        }
+    }
+}
+
+impl VertexShader for ModelShader {
+    type Input = Surface;
+    type Varying = Surface;
+    fn shade(&self, input: &Surface) -> (Vec4, Surface) {
+        let pos = transform_point(&self.model, input.pos);
+        let world = Surface {
+            pos,
+            normal: transform_normal(&self.inverse, input.normal),
+            uv: input.uv,
+        };
+        (self.view_projection * pos.extend(1.0), world)
+    }
+}
+
+// Diffuse light on a world space surface, for flat and Gouraud shading
+pub struct Diffuse<'a> {
+    pub lights: &'a [Light],
+    // Diffuse factor of the material
+    pub amount: FP,
+    pub eye: Vec3,
+    // Light whichever side of the surface faces the eye
+    pub two_sided: bool,
+}
+
+impl Diffuse<'_> {
+    pub fn intensity(&self, pos: Vec3, normal: Vec3) -> FP {
+        let normal = normal.normalize_or_zero();
+        let normal = match self.two_sided && normal.dot(self.eye - pos) < 0.0 {
+            true => -normal,
+            false => normal,
+        };
+        self.amount * illuminate(self.lights, pos, normal)
+    }
+}
+
+// Lights every vertex of an object space surface with its own normal, the light intensity
+// then gets interpolated along with the texture coordinates
+pub struct GouraudShader<'a> {
+    pub model: ModelShader,
+    pub light: Diffuse<'a>,
+}
+
+impl VertexShader for GouraudShader<'_> {
+    type Input = Surface;
+    type Varying = (Vec2, FP);
+    fn shade(&self, input: &Surface) -> (Vec4, (Vec2, FP)) {
+        let (clip, world) = self.model.shade(input);
+        let h = self.light.intensity(world.pos, world.normal);
+        (clip, (world.uv, h))
+    }
+}
+
+// Lights every pixel of a world space surface with Blinn-Phong
+pub struct BlinnPhongShader<'a> {
+    pub material: &'a Material,
+    // Only used when the surface has texture coordinates
+    pub texture: Option<&'a Texture>,
+    pub lights: &'a [Light],
+    pub eye: Vec3,
+    // Light whichever side of the surface faces the eye
+    pub two_sided: bool,
+}
+
+impl FragmentShader<Surface> for BlinnPhongShader<'_> {
+    fn shade(&self, fragment: &Fragment<Surface>) -> Option<Color> {
+        let material = self.material;
+        let s = &fragment.varying;
+        let base = match self.texture {
+            Some(texture) => {
+                let duv_dx = fragment.right().uv - s.uv;
+                let duv_dy = fragment.below().uv - s.uv;
+                let lod = texture.lod(duv_dx, duv_dy);
+                modulate(
+                    texture.sample_lod(s.uv, lod, material.filter),
+                    material.color,
+                )
+            }
+            None => material.color,
+        };
+        let to_eye = (self.eye - s.pos).normalize_or_zero();
+        let normal = match self.two_sided && s.normal.dot(to_eye) < 0.0 {
+            true => -s.normal.normalize_or_zero(),
+            false => s.normal.normalize_or_zero(),
+        };
+        let light = blinn_phong(self.lights, s.pos, normal, to_eye, material.shininess);
+        let lit = lerp_color(
+            Color::BLACK,
+            base,
+            (material.diffuse * light.diffuse).min(1.0),
+        );
+        // Highlights take on the color of the light, which is always white
+        Some(lerp_color(
+            lit,
+            Color::WHITE,
+            (material.specular * light.specular).min(1.0),
+        ))
+    }
+}
@@ -0,0 +1,94 @@
+use std::simd::Simd;
+
+use crate::camera::Camera;
+use crate::math::*;
+
+// Points handled by every SIMD operation
+pub const LANES: usize = 4;
+type Lanes = Simd<FP, LANES>;
+
+// Positions stored as structure of arrays, so that LANES of them are transformed at once.
+// The last group is padded with zeros.
+pub struct SoaPoints {
+    x: Vec<Lanes>,
+    y: Vec<Lanes>,
+    z: Vec<Lanes>,
+    len: usize,
+}
+
+impl SoaPoints {
+    pub fn new(points: &[Vec3]) -> SoaPoints {
+        let lanes = points.len().div_ceil(LANES);
+        let mut soa = SoaPoints {
+            x: Vec::with_capacity(lanes),
+            y: Vec::with_capacity(lanes),
+            z: Vec::with_capacity(lanes),
+            len: points.len(),
+        };
+        for chunk in points.chunks(LANES) {
+            let mut x = [0.0; LANES];
+            let mut y = [0.0; LANES];
+            let mut z = [0.0; LANES];
+            for (idx, p) in chunk.iter().enumerate() {
+                (x[idx], y[idx], z[idx]) = (p.x, p.y, p.z);
+            }
+            soa.x.push(Lanes::from_array(x));
+            soa.y.push(Lanes::from_array(y));
+            soa.z.push(Lanes::from_array(z));
+        }
+        soa
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    // Applies an affine transform, the bottom row of `m` is ignored
+    pub fn transform(&self, m: &Mat4) -> SoaPoints {
+        let row = |r: usize| {
+            let row = m.row(r);
+            [row.x, row.y, row.z, row.w].map(Lanes::splat)
+        };
+        let (r0, r1, r2) = (row(0), row(1), row(2));
+        let mut out = SoaPoints {
+            x: Vec::with_capacity(self.x.len()),
+            y: Vec::with_capacity(self.x.len()),
+            z: Vec::with_capacity(self.x.len()),
+            len: self.len,
+        };
+        for ((&x, &y), &z) in self.x.iter().zip(&self.y).zip(&self.z) {
+            out.x.push(r0[0] * x + r0[1] * y + r0[2] * z + r0[3]);
+            out.y.push(r1[0] * x + r1[1] * y + r1[2] * z + r1[3]);
+            out.z.push(r2[0] * x + r2[1] * y + r2[2] * z + r2[3]);
+        }
+        out
+    }
+    pub fn to_points(&self) -> Vec<Vec3> {
+        let mut points = Vec::with_capacity(self.x.len() * LANES);
+        for ((x, y), z) in self.x.iter().zip(&self.y).zip(&self.z) {
+            let (x, y, z) = (x.to_array(), y.to_array(), z.to_array());
+            points.extend((0..LANES).map(|idx| Vec3::new(x[idx], y[idx], z[idx])));
+        }
+        points.truncate(self.len);
+        points
+    }
+    // Applies a full 4x4 matrix, giving homogeneous coordinates
+    pub fn project(&self, m: &Mat4) -> Vec<Vec4> {
+        let rows = [0, 1, 2, 3].map(|r| {
+            let row = m.row(r);
+            [row.x, row.y, row.z, row.w].map(Lanes::splat)
+        });
+        let mut out = Vec::with_capacity(self.x.len() * LANES);
+        for ((&x, &y), &z) in self.x.iter().zip(&self.y).zip(&self.z) {
+            let [x, y, z, w] = rows.map(|r| (r[0] * x + r[1] * y + r[2] * z + r[3]).to_array());
+            out.extend((0..LANES).map(|idx| Vec4::new(x[idx], y[idx], z[idx], w[idx])));
+        }
+        out.truncate(self.len);
+        out
+    }
+    // Same as clip::to_clip for every point, which have to be in world space
+    pub fn to_clip(&self, camera: &Camera) -> Vec<Vec4> {
+        self.project(&camera.view_projection())
+    }
+}
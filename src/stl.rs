@@ -0,0 +1,159 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::math::*;
+use crate::meshes::{LoadError, Mesh, Z_UP_TO_WORLD};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+const HEADER_LEN: usize = 80;
+const TRIANGLE_LEN: usize = 50;
+
+pub fn load_stl(path: &Path) -> Result<Mesh, LoadError> {
+    parse_stl(&fs::read(path)?)
+}
+
+// Both variants are supported, identical vertices are merged into one
+pub fn parse_stl(data: &[u8]) -> Result<Mesh, LoadError> {
+    // Binary files may also start with "solid", so check whether the size matches first
+    let soup = match binary_triangle_count(data) {
+        Some(count) if data.len() == HEADER_LEN + 4 + count * TRIANGLE_LEN => {
+            parse_binary(data, count)
+        }
+        _ if data.starts_with(b"solid") => parse_ascii(data)?,
+        _ => return Err(LoadError::Parse("not a valid STL file".to_string())),
+    };
+    Ok(Mesh::from_soup(&soup))
+}
+
+fn binary_triangle_count(data: &[u8]) -> Option<usize> {
+    let count = data.get(HEADER_LEN..HEADER_LEN + 4)?;
+    Some(u32::from_le_bytes(count.try_into().unwrap()) as usize)
+}
+
+fn parse_binary(data: &[u8], count: usize) -> Vec<Vec3> {
+    let read_vec = |bytes: &[u8]| {
+        let f = |idx: usize| f32::from_le_bytes(bytes[idx * 4..idx * 4 + 4].try_into().unwrap());
+        Vec3::new(f(0) as FP, f(1) as FP, f(2) as FP)
+    };
+    data[HEADER_LEN + 4..]
+        .chunks_exact(TRIANGLE_LEN)
+        .take(count)
+        // Skip the stored normal, the winding order is what counts
+        .flat_map(|tri| (1..4).map(move |idx| read_vec(&tri[idx * 12..idx * 12 + 12])))
+        .collect()
+}
+
+fn parse_ascii(data: &[u8]) -> Result<Vec<Vec3>, LoadError> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| LoadError::Parse("ASCII STL is not valid UTF-8".to_string()))?;
+    let mut soup = Vec::new();
+    let mut facet_len = 0;
+    let mut tokens = text.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut coord = || -> Result<FP, LoadError> {
+                    tokens
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| LoadError::Parse("invalid vertex".to_string()))
+                };
+                soup.push(Vec3::new(coord()?, coord()?, coord()?));
+                facet_len += 1;
+            }
+            "endfacet" => {
+                if facet_len != 3 {
+                    return Err(LoadError::Parse(format!(
+                        "facet has {} vertices instead of 3",
+                        facet_len
+                    )));
+                }
+                facet_len = 0;
+            }
+            _ => {}
+        }
+    }
+    // A file cut off in the middle of a facet
+    if facet_len != 0 || soup.len() % 3 != 0 {
+        return Err(LoadError::Parse("unterminated facet".to_string()));
+    }
+    Ok(soup)
+}
+
+pub fn save_stl(
+    mesh: &Mesh,
+    transform: &Affine3,
+    path: &Path,
+    format: StlFormat,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_stl(mesh, transform, &mut writer, format)?;
+    writer.flush()
+}
+
+// Writes the mesh as placed in the world by `transform`, turned so that +z is up the way slicers
+// expect. Loading the file with Z_UP_TO_WORLD puts it back where it was.
+pub fn write_stl(
+    mesh: &Mesh,
+    transform: &Affine3,
+    writer: &mut impl Write,
+    format: StlFormat,
+) -> io::Result<()> {
+    let to_file = |v: Vec3| Z_UP_TO_WORLD.inverse() * transform_point(transform, v);
+    // A mirroring transform turns the faces inside out
+    let mirrored = transform.matrix3.determinant() < 0.0;
+    let triangles = mesh.triangles().map(|[a, b, c]| {
+        let corners = match mirrored {
+            true => [a, c, b],
+            false => [a, b, c],
+        };
+        let tri = corners.map(|idx| to_file(mesh.vertices[idx]));
+        let normal = (tri[1] - tri[0]).cross(tri[2] - tri[0]).normalize_or_zero();
+        (normal, tri)
+    });
+    match format {
+        StlFormat::Ascii => {
+            writeln!(writer, "solid mesh")?;
+            for (normal, tri) in triangles {
+                writeln!(
+                    writer,
+                    "  facet normal {:e} {:e} {:e}",
+                    normal.x, normal.y, normal.z
+                )?;
+                writeln!(writer, "    outer loop")?;
+                for v in tri {
+                    writeln!(writer, "      vertex {:e} {:e} {:e}", v.x, v.y, v.z)?;
+                }
+                writeln!(writer, "    endloop")?;
+                writeln!(writer, "  endfacet")?;
+            }
+            writeln!(writer, "endsolid mesh")
+        }
+        StlFormat::Binary => {
+            let mut header = [0; HEADER_LEN];
+            header[..10].copy_from_slice(b"binary STL");
+            writer.write_all(&header)?;
+            writer.write_all(&(mesh.triangle_count() as u32).to_le_bytes())?;
+            for (normal, tri) in triangles {
+                for v in std::iter::once(normal).chain(tri) {
+                    for c in v.to_array() {
+                        // Only does anything when FP is f64
+                        #[allow(clippy::unnecessary_cast)]
+                        let c = c as f32;
+                        writer.write_all(&c.to_le_bytes())?;
+                    }
+                }
+                // Attribute byte count, unused
+                writer.write_all(&[0, 0])?;
+            }
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{Texture, WindowCanvas};
+
+use crate::math::FP;
+
+/// Anything the rasterizers can plot pixels into
+pub trait RenderTarget {
+    fn size(&self) -> (u32, u32);
+    fn put_pixel(&mut self, p: Point, c: Color);
+    fn clear(&mut self, c: Color);
+}
+
+impl RenderTarget for WindowCanvas {
+    fn size(&self) -> (u32, u32) {
+        self.output_size().unwrap()
+    }
+    fn put_pixel(&mut self, p: Point, c: Color) {
+        self.set_draw_color(c);
+        self.draw_point(p).unwrap();
+    }
+    fn clear(&mut self, c: Color) {
+        self.set_draw_color(c);
+        WindowCanvas::clear(self);
+    }
+}
+
+/// Headless in-memory target, pixels are stored row-major as 0xAARRGGBB.
+/// That is SDL's packed ARGB8888, so frames go to a texture without converting every pixel,
+/// `to_rgba` gives the bytes in RGBA order for anything else.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.pixels.clear();
+        self.pixels.resize((width * height) as usize, 0);
+    }
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+    pub fn pixels_mut(&mut self) -> &mut [u32] {
+        &mut self.pixels
+    }
+    pub fn get_pixel(&self, p: Point) -> Option<Color> {
+        self.index(p).map(|idx| unpack_argb(self.pixels[idx]))
+    }
+    /// Pixels as tightly packed RGBA bytes, e.g. for writing out an image
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&px| {
+                let c = unpack_argb(px);
+                [c.r, c.g, c.b, c.a]
+            })
+            .collect()
+    }
+    fn index(&self, p: Point) -> Option<usize> {
+        if p.x < 0 || p.y < 0 || p.x as u32 >= self.width || p.y as u32 >= self.height {
+            return None;
+        }
+        Some(p.y as usize * self.width as usize + p.x as usize)
+    }
+}
+
+impl RenderTarget for Framebuffer {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+    fn put_pixel(&mut self, p: Point, c: Color) {
+        // Out of bounds points are dropped, same as SDL does
+        if let Some(idx) = self.index(p) {
+            self.pixels[idx] = pack_argb(c);
+        }
+    }
+    fn clear(&mut self, c: Color) {
+        let px = pack_argb(c);
+        self.pixels.iter_mut().for_each(|p| *p = px);
+    }
+}
+
+/// A rectangle of another target, drawn to as if it was a target of its own.
+/// Pixels outside of the rectangle are dropped.
+pub struct Viewport<'a, T: RenderTarget> {
+    target: &'a mut T,
+    rect: Rect,
+}
+
+impl<'a, T: RenderTarget> Viewport<'a, T> {
+    pub fn new(target: &'a mut T, rect: Rect) -> Viewport<'a, T> {
+        Viewport { target, rect }
+    }
+}
+
+impl<T: RenderTarget> RenderTarget for Viewport<'_, T> {
+    fn size(&self) -> (u32, u32) {
+        self.rect.size()
+    }
+    fn put_pixel(&mut self, p: Point, c: Color) {
+        if p.x < 0 || p.y < 0 || p.x as u32 >= self.rect.width() || p.y as u32 >= self.rect.height()
+        {
+            return;
+        }
+        self.target.put_pixel(p + self.rect.top_left(), c);
+    }
+    fn clear(&mut self, c: Color) {
+        for y in 0..self.rect.height() as i32 {
+            for x in 0..self.rect.width() as i32 {
+                self.target
+                    .put_pixel(Point::new(x, y) + self.rect.top_left(), c);
+            }
+        }
+    }
+}
+
+/// Shows the framebuffer in the window. Everything goes through `texture` at once, which has to be
+/// a streaming texture in ARGB8888 of the same size.
+pub fn present(canvas: &mut WindowCanvas, texture: &mut Texture, frame: &Framebuffer) {
+    let row_len = frame.width as usize;
+    texture
+        .with_lock(None, |bytes, pitch| {
+            for (row, pixels) in frame.pixels.chunks_exact(row_len).enumerate() {
+                let start = row * pitch;
+                let dst = &mut bytes[start..start + row_len * 4];
+                for (dst, px) in dst.chunks_exact_mut(4).zip(pixels) {
+                    dst.copy_from_slice(&px.to_ne_bytes());
+                }
+            }
+        })
+        .unwrap();
+    canvas.copy(texture, None, None).unwrap();
+    canvas.present();
+}
+
+pub fn pack_argb(c: Color) -> u32 {
+    (c.a as u32) << 24 | (c.r as u32) << 16 | (c.g as u32) << 8 | c.b as u32
+}
+
+pub fn unpack_argb(px: u32) -> Color {
+    Color::RGBA(
+        (px >> 16) as u8,
+        (px >> 8) as u8,
+        px as u8,
+        (px >> 24) as u8,
+    )
+}
+
+/// Per-pixel depth as returned by clip_to_screen, (w - z) / w of the clip space position.
+/// That is 1 at the near plane and 0 at the far plane, so larger values are closer to the camera.
+/// It can be interpolated linearly in screen space, being linear in 1/z for perspective
+/// projections and linear in z for orthographic ones.
+pub struct DepthBuffer {
+    width: u32,
+    height: u32,
+    depth: Vec<FP>,
+}
+
+impl DepthBuffer {
+    pub fn new(width: u32, height: u32) -> DepthBuffer {
+        DepthBuffer {
+            width,
+            height,
+            depth: vec![0.0; (width * height) as usize],
+        }
+    }
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.depth.clear();
+        self.depth.resize((width * height) as usize, 0.0);
+    }
+    pub fn clear(&mut self) {
+        self.depth.iter_mut().for_each(|d| *d = 0.0);
+    }
+    /// Depths row-major, like the pixels of a Framebuffer
+    pub fn values(&self) -> &[FP] {
+        &self.depth
+    }
+    pub fn values_mut(&mut self) -> &mut [FP] {
+        &mut self.depth
+    }
+    fn index(&self, p: Point) -> Option<usize> {
+        if p.x < 0 || p.y < 0 || p.x as u32 >= self.width || p.y as u32 >= self.height {
+            return None;
+        }
+        Some(p.y as usize * self.width as usize + p.x as usize)
+    }
+    /// Returns true if `depth` is closer than what is already at `p`, without storing it
+    pub fn test(&self, p: Point, depth: FP) -> bool {
+        self.index(p).is_some_and(|idx| depth > self.depth[idx])
+    }
+    /// Returns true and stores the depth if `depth` is closer than what is already at `p`
+    pub fn test_and_set(&mut self, p: Point, depth: FP) -> bool {
+        let idx = match self.index(p) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        if depth <= self.depth[idx] {
+            return false;
+        }
+        self.depth[idx] = depth;
+        true
+    }
+}
@@ -0,0 +1,1576 @@
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::{MouseState, MouseWheelDirection};
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+
+use crate::camera::*;
+use crate::clip::*;
+use crate::controls::*;
+use crate::flatshapes::*;
+use crate::light::*;
+
+use crate::material::Material;
+use crate::math::*;
+use crate::meshes::*;
+use crate::multiview::*;
+use crate::obj::*;
+use crate::object::*;
+use crate::pipeline::*;
+use crate::ply::*;
+use crate::scene::Scene;
+use crate::stl::*;
+use crate::target::*;
+use crate::texture::*;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/fixtures")
+        .join(name)
+}
+
+#[test]
+fn framebuffer_pixels() {
+    let mut canvas = Framebuffer::new(4, 3);
+    assert_eq!(canvas.size(), (4, 3));
+    canvas.clear(Color::BLUE);
+    assert!(canvas
+        .pixels()
+        .iter()
+        .all(|&px| px == pack_argb(Color::BLUE)));
+    canvas.put_pixel(Point::new(3, 2), Color::RED);
+    assert_eq!(canvas.get_pixel(Point::new(3, 2)), Some(Color::RED));
+    assert_eq!(canvas.pixels()[11], 0xFFFF0000);
+    // Out of bounds pixels are dropped and can't be read
+    for p in [(-1, 0), (0, -1), (4, 0), (0, 3)] {
+        canvas.put_pixel(Point::new(p.0, p.1), Color::RED);
+        assert_eq!(canvas.get_pixel(Point::new(p.0, p.1)), None);
+    }
+    let red = canvas
+        .pixels()
+        .iter()
+        .filter(|&&px| px == 0xFFFF0000)
+        .count();
+    assert_eq!(red, 1);
+    assert_eq!(&canvas.to_rgba()[44..], &[255, 0, 0, 255]);
+
+    canvas.resize(2, 5);
+    assert_eq!(canvas.size(), (2, 5));
+    assert_eq!(canvas.pixels(), &[0; 10]);
+    assert_eq!(
+        canvas.get_pixel(Point::new(1, 4)),
+        Some(Color::RGBA(0, 0, 0, 0))
+    );
+    assert_eq!(canvas.get_pixel(Point::new(2, 0)), None);
+}
+
+#[test]
+fn argb_round_trip() {
+    let c = Color::RGBA(0x12, 0x34, 0x56, 0x78);
+    assert_eq!(pack_argb(c), 0x78123456);
+    assert_eq!(unpack_argb(pack_argb(c)), c);
+    assert_eq!(pack_argb(unpack_argb(0xDEADBEEF)), 0xDEADBEEF);
+}
+
+#[test]
+fn depth_tested_fills() {
+    // Larger depths are closer, the near triangle only covers the left part of the far one
+    let near = || {
+        [
+            Xyzh::new(5.0, 5.0, 0.5, 1.0),
+            Xyzh::new(30.0, 5.0, 0.5, 1.0),
+            Xyzh::new(5.0, 30.0, 0.5, 1.0),
+        ]
+    };
+    let far = || {
+        [
+            Xyzh::new(0.0, 0.0, 0.25, 1.0),
+            Xyzh::new(60.0, 0.0, 0.25, 1.0),
+            Xyzh::new(0.0, 60.0, 0.25, 1.0),
+        ]
+    };
+    for near_first in [true, false] {
+        let mut canvas = Framebuffer::new(64, 64);
+        let mut depth = DepthBuffer::new(64, 64);
+        let mut draw = |[p0, p1, p2]: [Xyzh; 3], color| {
+            draw_depth_triangle(&mut canvas, &mut depth, p0, p1, p2, color)
+        };
+        match near_first {
+            true => {
+                draw(near(), Color::RED);
+                draw(far(), Color::BLUE);
+            }
+            false => {
+                draw(far(), Color::BLUE);
+                draw(near(), Color::RED);
+            }
+        }
+        assert_eq!(canvas.get_pixel(Point::new(10, 10)), Some(Color::RED));
+        assert_eq!(canvas.get_pixel(Point::new(35, 10)), Some(Color::BLUE));
+        assert_eq!(canvas.get_pixel(Point::new(2, 2)), Some(Color::BLUE));
+        assert_eq!(depth.values()[10 * 64 + 10], 0.5);
+    }
+}
+
+#[test]
+fn clip_near_plane() {
+    let camera = Camera::new(None, None, None, (100, 100));
+    let clip = |points: [Vec3; 3]| points.map(|p| to_clip(&camera, &p));
+    let inside = |polygon: &[Vec4]| {
+        polygon.iter().all(|&v| {
+            ClipPlane::FRUSTUM
+                .iter()
+                .all(|plane| plane.distance(v) >= -1e-9)
+        })
+    };
+    // One vertex behind the camera leaves a quad
+    let one_behind = clip([
+        Vec3::new(0.0, -0.5, 2.0),
+        Vec3::new(0.5, 0.5, 2.0),
+        Vec3::new(0.0, 0.0, -1.0),
+    ]);
+    let polygon = clip_polygon(&one_behind, ClipPlane::FRUSTUM);
+    assert_eq!(polygon.len(), 4);
+    assert!(inside(&polygon));
+    assert_eq!(
+        polygon.iter().filter(|v| v.z.abs() < 1e-9).count(),
+        2,
+        "two new vertices on the near plane"
+    );
+    // Only the edge between those two runs along the near plane instead of the triangle
+    let edges = clip_polygon_edges(&one_behind, ClipPlane::FRUSTUM);
+    let added: Vec<usize> = (0..4).filter(|&idx| !edges[idx].1).collect();
+    assert_eq!(added.len(), 1);
+    assert!(edges[added[0]].0.z.abs() < 1e-9);
+    assert!(edges[(added[0] + 1) % 4].0.z.abs() < 1e-9);
+
+    // Two behind leave a smaller triangle
+    let two_behind = clip([
+        Vec3::new(0.0, 0.0, 3.0),
+        Vec3::new(0.5, 0.0, -1.0),
+        Vec3::new(-0.5, 0.5, 0.0),
+    ]);
+    let polygon = clip_polygon(&two_behind, ClipPlane::FRUSTUM);
+    assert_eq!(polygon.len(), 3);
+    assert!(inside(&polygon));
+    assert_eq!(polygon[0], two_behind[0]);
+
+    // Entirely behind is gone, entirely inside is untouched
+    let behind = clip([-Vec3::Z, Vec3::new(1.0, 0.0, -2.0), -Vec3::Y]);
+    assert!(clip_polygon(&behind, ClipPlane::FRUSTUM).is_empty());
+    let visible = clip([
+        Vec3::new(0.0, 0.0, 2.0),
+        Vec3::new(1.0, 0.0, 2.0),
+        Vec3::Z * 3.0,
+    ]);
+    assert_eq!(clip_polygon(&visible, ClipPlane::FRUSTUM), visible.to_vec());
+}
+
+#[test]
+fn wireframe_clipped_edges() {
+    let mut canvas = Framebuffer::new(100, 100);
+    canvas.clear(Color::WHITE);
+    let camera = Camera::new(None, None, None, (100, 100));
+    // Covers the whole screen with all of its edges off screen
+    let mesh = Mesh {
+        vertices: vec![
+            Vec3::new(-100.0, -100.0, 5.0),
+            Vec3::new(100.0, -100.0, 5.0),
+            Vec3::new(0.0, 300.0, 5.0),
+        ],
+        indices: vec![0, 1, 2],
+        ..Mesh::default()
+    };
+    let stats = Object::new(Rc::new(mesh)).render(&mut canvas, &camera);
+    assert_eq!(stats.drawn, 1);
+    assert!(canvas
+        .pixels()
+        .iter()
+        .all(|&px| px == pack_argb(Color::WHITE)));
+}
+
+#[test]
+fn culling_stats() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let mesh = Rc::new(teapot());
+    let total = mesh.triangle_count();
+    let mut stats = |cull, winding| {
+        let teapot = Object {
+            transform: affine(Vec3::splat(4.0), Quat::IDENTITY, Vec3::ZERO),
+            cull,
+            winding,
+            ..Object::new(mesh.clone())
+        };
+        teapot.render(&mut canvas, &camera)
+    };
+    let none = stats(CullMode::None, Winding::CounterClockwise);
+    assert_eq!((none.drawn, none.culled), (total, 0));
+
+    let back = stats(CullMode::Back, Winding::CounterClockwise);
+    let front = stats(CullMode::Front, Winding::CounterClockwise);
+    assert!(back.culled > 0 && back.drawn > 0);
+    assert_eq!(back.drawn + back.culled, total);
+    assert_eq!(front.drawn + front.culled, total);
+    // Faces seen edge on are culled either way
+    assert_eq!(front.culled, back.drawn);
+
+    // Flipping the winding swaps which faces are in front
+    let flipped = stats(CullMode::Back, Winding::Clockwise);
+    assert_eq!(flipped.drawn + flipped.culled, total);
+    assert!(flipped.drawn > 0 && flipped.drawn + back.drawn <= total);
+    assert_eq!(
+        stats(CullMode::Front, Winding::Clockwise).culled,
+        flipped.drawn
+    );
+
+    // Triangles clipped away entirely are neither drawn nor culled
+    let behind = Object {
+        transform: affine(Vec3::ONE, Quat::IDENTITY, Vec3::new(0.0, 0.0, -20.0)),
+        ..Object::new(mesh.clone())
+    };
+    assert_eq!(behind.render(&mut canvas, &camera), RenderStats::default());
+}
+
+#[test]
+fn soup_merges_vertices() {
+    for mesh in [cube(), teapot()] {
+        let soup: Vec<Vec3> = mesh
+            .triangles()
+            .flat_map(|tri| tri.map(|idx| mesh.vertices[idx]))
+            .collect();
+        let merged = Mesh::from_soup(&soup);
+        assert_same_triangles(&mesh, &merged);
+        // Corners shared by several triangles are only stored once
+        assert!(merged.vertices.len() < soup.len());
+        assert_eq!(merged.vertices.len(), mesh.vertices.len());
+        merged.validate().unwrap();
+    }
+}
+
+#[test]
+fn gltf_hierarchy() {
+    // A parent node moved along x and scaled up, with a child moved along z, both showing the
+    // same triangle twice with two primitives
+    let objects = import_scene(&fixture("hierarchy.gltf")).unwrap();
+    assert_eq!(objects.len(), 4);
+    let world = |obj: &Object, idx: usize| transform_point(&obj.transform, obj.mesh.vertices[idx]);
+    for (obj, primitive) in objects.iter().zip([0, 1, 0, 1]) {
+        assert_eq!(obj.mesh.indices, vec![0, 1, 2]);
+        assert!(Rc::ptr_eq(&obj.mesh, &objects[primitive].mesh));
+    }
+    // glTF has y up and z towards the viewer, world space has both the other way
+    let close = |a: Vec3, b: Vec3| a.abs_diff_eq(b, 1e-6);
+    for parent in &objects[..2] {
+        assert!(close(world(parent, 0), Vec3::new(1.0, 0.0, 0.0)));
+        assert!(close(world(parent, 1), Vec3::new(3.0, 0.0, 0.0)));
+        assert!(close(world(parent, 2), Vec3::new(1.0, -2.0, 0.0)));
+    }
+    for child in &objects[2..] {
+        assert!(close(world(child, 0), Vec3::new(1.0, 0.0, -10.0)));
+        assert!(close(world(child, 2), Vec3::new(1.0, -2.0, -10.0)));
+    }
+}
+
+#[test]
+fn gltf_errors() {
+    assert!(matches!(
+        import_scene(&fixture("missing.gltf")),
+        Err(LoadError::Io(_))
+    ));
+    assert!(matches!(
+        import_scene(&fixture("broken.gltf")),
+        Err(LoadError::Parse(_))
+    ));
+    assert!(matches!(
+        import_scene(&fixture("lines.gltf")),
+        Err(LoadError::UnsupportedMode(gltf::mesh::Mode::Lines))
+    ));
+    // Caught by the glTF crate's validation before read_primitive gets to check
+    match import_mesh(&fixture("no_position.gltf")) {
+        Err(LoadError::Parse(msg)) => assert!(msg.contains("POSITION"), "{}", msg),
+        other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn obj_quad() {
+    let obj = load_obj(&fixture("square.obj")).unwrap();
+    assert_eq!(obj.models.len(), 1);
+    let mesh = &obj.models[0].mesh;
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.triangle_count(), 2);
+    // v is flipped so that textures are read from the top
+    assert_eq!(mesh.uvs.as_ref().unwrap()[2], Vec2::new(1.0, 0.0));
+    assert_eq!(mesh.normals.as_ref().unwrap()[0], Vec3::Z);
+    mesh.validate().unwrap();
+}
+
+#[test]
+fn obj_groups() {
+    let obj = load_obj(&fixture("groups.obj")).unwrap();
+    assert_eq!(obj.mtllibs, vec!["shapes.mtl"]);
+    assert_eq!(obj.models.len(), 3);
+
+    let lshape = &obj.models[0];
+    assert_eq!(lshape.object.as_deref(), Some("lshape"));
+    assert_eq!(lshape.material.as_deref(), Some("red"));
+    assert_eq!(lshape.mesh.triangle_count(), 4);
+    // Concave polygon must not be triangulated over its notch at (1.5, 1.5)
+    let area: FP = lshape
+        .mesh
+        .triangles()
+        .map(|[a, b, c]| {
+            let v = &lshape.mesh.vertices;
+            (v[b] - v[a]).cross(v[c] - v[a]).z / 2.0
+        })
+        .sum();
+    assert!((area - 3.0).abs() < 1e-9);
+
+    let tris = &obj.models[1];
+    assert_eq!(tris.object.as_deref(), Some("triangles"));
+    assert_eq!(tris.material.as_deref(), Some("blue"));
+    assert_eq!(tris.mesh.vertices[0], Vec3::new(0.0, 0.0, 1.0));
+    assert_eq!(obj.models[2].group.as_deref(), Some("back"));
+    assert_eq!(obj.models[2].material.as_deref(), Some("blue"));
+}
+
+#[test]
+fn obj_materials() {
+    let obj = load_obj(&fixture("groups.obj")).unwrap();
+    let materials = obj.load_materials(&fixture(""));
+    assert_eq!(materials["red"].color, Color::RGB(255, 0, 0));
+    assert!(materials["red"].texture.is_none());
+    let objects = obj.into_objects(&materials);
+    assert_eq!(objects[1].material.color, Color::RGBA(0, 0, 255, 128));
+    let texture = objects[2].material.texture.as_ref().unwrap();
+    assert_eq!(texture.size(), (2, 2));
+    assert_eq!(texture.texel(1, 0), Color::WHITE);
+    assert!(matches!(
+        parse_mtl("Kd 1 0 0\n".as_bytes(), Path::new("")),
+        Err(LoadError::Parse(_))
+    ));
+}
+
+#[test]
+fn obj_missing_materials() {
+    // A missing library leaves the others, and the geometry, usable
+    let mut obj = load_obj(&fixture("groups.obj")).unwrap();
+    obj.mtllibs.insert(0, "missing.mtl".to_string());
+    let materials = obj.load_materials(&fixture(""));
+    assert_eq!(materials["red"].color, Color::RGB(255, 0, 0));
+    let objects = obj.into_objects(&materials);
+    assert_eq!(objects.len(), 3);
+    // So does a missing texture, for the rest of its material
+    let mtl = "newmtl gone\nKd 0 1 0\nmap_Kd missing.png\n";
+    let materials = parse_mtl(mtl.as_bytes(), &fixture("")).unwrap();
+    assert_eq!(materials["gone"].color, Color::RGB(0, 255, 0));
+    assert!(materials["gone"].texture.is_none());
+}
+
+#[test]
+fn obj_errors() {
+    let bad_index = parse_obj("v 0 0 0\nf 1 2 3\n".as_bytes());
+    assert!(matches!(bad_index, Err(LoadError::Parse(_))));
+    let bad_number = parse_obj("v 0 zero 0\n".as_bytes());
+    assert!(matches!(bad_number, Err(LoadError::Parse(_))));
+    assert!(matches!(
+        load_obj(&fixture("missing.obj")),
+        Err(LoadError::Io(_))
+    ));
+}
+
+fn assert_same_triangles(a: &Mesh, b: &Mesh) {
+    assert_eq!(a.triangle_count(), b.triangle_count());
+    for (tri_a, tri_b) in a.triangles().zip(b.triangles()) {
+        for (&idx_a, &idx_b) in tri_a.iter().zip(tri_b.iter()) {
+            assert!((a.vertices[idx_a] - b.vertices[idx_b]).length() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn stl_round_trip() {
+    for mesh in [cube(), teapot()] {
+        for format in [StlFormat::Ascii, StlFormat::Binary] {
+            let mut data = Vec::new();
+            write_stl(&mesh, &Affine3::IDENTITY, &mut data, format).unwrap();
+            let loaded = parse_stl(&data).unwrap();
+            assert_eq!(loaded.vertices.len(), mesh.vertices.len());
+            // Placed in the world the way STL files get loaded, it ends up where it started
+            let object = Object::from_matrix(Rc::new(loaded), Mat4::from_quat(Z_UP_TO_WORLD));
+            let placed = Mesh {
+                vertices: object
+                    .mesh
+                    .vertices
+                    .iter()
+                    .map(|&v| transform_point(&object.transform, v))
+                    .collect(),
+                ..(*object.mesh).clone()
+            };
+            assert_same_triangles(&mesh, &placed);
+        }
+    }
+}
+
+#[test]
+fn stl_export_z_up() {
+    // The teapot's lid is at -Y in world space, it should end up at +Z for slicers
+    let mut data = Vec::new();
+    write_stl(&teapot(), &Affine3::IDENTITY, &mut data, StlFormat::Binary).unwrap();
+    let loaded = parse_stl(&data).unwrap();
+    let top = loaded
+        .vertices
+        .iter()
+        .fold(Vec3::splat(FP::MIN), |acc, v| acc.max(*v));
+    assert!((top.z - 1.575).abs() < 1e-3);
+
+    // The transform of the object is exported with it, keeping mirrored faces facing out
+    let mirror = affine(Vec3::new(-1.0, 1.0, 1.0), Quat::IDENTITY, Vec3::ZERO);
+    let mut data = Vec::new();
+    write_stl(&cube(), &mirror, &mut data, StlFormat::Binary).unwrap();
+    let loaded = parse_stl(&data).unwrap();
+    // The cube is centered on the origin, so outward is away from it
+    for [a, b, c] in loaded.triangles() {
+        let [a, b, c] = [a, b, c].map(|idx| loaded.vertices[idx]);
+        assert!((b - a).cross(c - a).dot(a) > 0.0);
+    }
+}
+
+#[test]
+fn stl_ascii() {
+    let data = b"solid tri
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid tri
+";
+    let mesh = parse_stl(data).unwrap();
+    assert_eq!(mesh.triangle_count(), 1);
+    assert_eq!(mesh.vertices[1], Vec3::X);
+    assert!(matches!(
+        parse_stl(b"solid broken\n vertex 0 0\n"),
+        Err(LoadError::Parse(_))
+    ));
+    assert!(matches!(parse_stl(b"garbage"), Err(LoadError::Parse(_))));
+    // Cut off after two vertices of the last facet
+    let truncated = b"solid tri
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+";
+    assert!(matches!(parse_stl(truncated), Err(LoadError::Parse(_))));
+}
+
+#[test]
+fn ply_ascii_points() {
+    let mesh = load_ply(&fixture("points.ply")).unwrap();
+    assert!(mesh.is_point_cloud());
+    assert_eq!(mesh.vertices.len(), 3);
+    assert_eq!(mesh.vertices[2], Vec3::Y);
+    let colors = mesh.colors.unwrap();
+    assert_eq!(colors[1], Color::RGB(0, 255, 0));
+}
+
+#[test]
+fn ply_ascii_mesh() {
+    let mesh = load_ply(&fixture("quad.ply")).unwrap();
+    assert!(!mesh.is_point_cloud());
+    assert_eq!(mesh.triangle_count(), 2);
+    assert_eq!(mesh.normals.unwrap()[3], Vec3::Z);
+}
+
+#[test]
+fn ply_invalid_indices() {
+    let header = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar float vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+";
+    assert!(parse_ply(format!("{}3 0 1 2\n", header).as_bytes()).is_ok());
+    for face in ["3 0 1 -1", "3 0 1 1.5", "3 0 1 nan", "3 0 1 5e9"] {
+        let data = format!("{}{}\n", header, face);
+        assert!(
+            matches!(parse_ply(data.as_bytes()), Err(LoadError::Parse(_))),
+            "{}",
+            face
+        );
+    }
+}
+
+// Same triangle with float colors in both binary variants
+fn binary_ply(
+    format: &str,
+    to_bytes: fn(f32) -> [u8; 4],
+    index_bytes: fn(i32) -> [u8; 4],
+) -> Vec<u8> {
+    let mut data = format!(
+        "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+         property float z\nproperty float red\nproperty float green\nproperty float blue\n\
+         element face 1\nproperty list uchar int vertex_indices\nend_header\n",
+        format
+    )
+    .into_bytes();
+    for v in [[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]] {
+        for c in v.iter().chain(&[1.0, 0.5, 0.0]) {
+            data.extend_from_slice(&to_bytes(*c));
+        }
+    }
+    data.push(3);
+    for idx in 0..3 {
+        data.extend_from_slice(&index_bytes(idx));
+    }
+    data
+}
+
+#[test]
+fn ply_binary() {
+    let le = binary_ply("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+    let be = binary_ply("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+    let mut truncated = le.clone();
+    truncated.truncate(le.len() - 5);
+    assert!(matches!(parse_ply(&truncated), Err(LoadError::Parse(_))));
+    for data in [le, be] {
+        let mesh = parse_ply(&data).unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+        assert_eq!(mesh.vertices[1], Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(mesh.colors.unwrap()[2], Color::RGB(255, 128, 0));
+    }
+}
+
+#[test]
+fn point_cloud_splats() {
+    let mut canvas = Framebuffer::new(100, 100);
+    canvas.clear(Color::WHITE);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let cloud = Object::new(Rc::new(load_ply(&fixture("points.ply")).unwrap()));
+    assert_eq!(cloud.render(&mut canvas, &camera).drawn, 3);
+    assert_eq!(
+        canvas.get_pixel(Point::new(50, 50)),
+        Some(Color::RGB(255, 0, 0))
+    );
+}
+
+#[test]
+fn texture_sampling() {
+    let texture = Texture::load(&fixture("checker.png")).unwrap();
+    let center = Vec2::new(0.25, 0.25);
+    assert_eq!(texture.sample(center, Filter::Nearest), Color::BLACK);
+    assert_eq!(texture.sample(center, Filter::Bilinear), Color::BLACK);
+    // Halfway between texel centers, bilinear blends all four
+    let between = Vec2::new(0.5, 0.5);
+    assert_eq!(texture.sample(between, Filter::Nearest), Color::BLACK);
+    assert_eq!(
+        texture.sample(between, Filter::Bilinear),
+        Color::RGB(128, 128, 128)
+    );
+    // Coordinates wrap around
+    assert_eq!(
+        texture.sample(Vec2::new(1.75, -0.75), Filter::Nearest),
+        Color::WHITE
+    );
+}
+
+#[test]
+fn textured_quad_perspective() {
+    let mut canvas = Framebuffer::new(100, 100);
+    canvas.clear(Color::RED);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    // Left half black and right half white, on a quad receding to the right.
+    // The middle of the quad is at x = 0, which is left of the middle of its projection.
+    let mesh = Mesh {
+        vertices: vec![
+            Vec3::new(-2.0, -2.0, 0.0),
+            Vec3::new(2.0, -2.0, 28.0),
+            Vec3::new(2.0, 2.0, 28.0),
+            Vec3::new(-2.0, 2.0, 0.0),
+        ],
+        indices: vec![0, 1, 2, 0, 2, 3],
+        uvs: Some(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ]),
+        ..Mesh::default()
+    };
+    let texture = Texture::new(2, 1, vec![Color::BLACK, Color::WHITE]);
+    let quad = Object {
+        material: Material {
+            texture: Some(Arc::new(texture)),
+            filter: Filter::Nearest,
+            ..Material::default()
+        },
+        ..Object::new(Rc::new(mesh))
+    };
+    quad.render_solid(&mut canvas, &mut depth, &camera, &[]);
+    let shade = |x| canvas.get_pixel(Point::new(x, 50)).unwrap().r;
+    // Affine interpolation would put the edge around x = 46.
+    // Both halves of the quad are lit differently, so only tell black from lit white.
+    assert_eq!(shade(42), 0);
+    assert_eq!(shade(48), 0);
+    assert!(shade(52) > 0);
+    assert!(shade(60) > 0);
+}
+
+fn checkerboard(size: u32) -> Texture {
+    let texels = (0..size * size)
+        .map(|idx| match (idx / size + idx % size) % 2 {
+            0 => Color::BLACK,
+            _ => Color::WHITE,
+        })
+        .collect();
+    Texture::new(size, size, texels)
+}
+
+#[test]
+fn mip_chain() {
+    let texture = Texture::new(4, 2, vec![Color::WHITE; 8]);
+    assert_eq!(texture.mip_count(), 3);
+    assert_eq!(texture.mip_size(1), (2, 1));
+    assert_eq!(texture.mip_size(2), (1, 1));
+
+    let checker = checkerboard(4);
+    let gray = Color::RGB(128, 128, 128);
+    let uv = Vec2::new(0.125, 0.125);
+    assert_eq!(checker.sample_lod(uv, 0.0, Filter::Nearest), Color::BLACK);
+    assert_eq!(checker.sample_lod(uv, 1.0, Filter::Nearest), gray);
+    assert_eq!(checker.sample_lod(uv, 10.0, Filter::Bilinear), gray);
+    assert_eq!(
+        checker.sample_lod(uv, 0.5, Filter::Trilinear),
+        Color::RGB(64, 64, 64)
+    );
+    // One texel per pixel is full resolution, two per pixel the next level
+    assert_eq!(checker.lod(Vec2::new(0.25, 0.0), Vec2::new(0.0, 0.25)), 0.0);
+    assert_eq!(checker.lod(Vec2::new(0.5, 0.0), Vec2::ZERO), 1.0);
+}
+
+#[test]
+fn trilinear_far_plane() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    // About 20 pixels across for 64 texels
+    let mesh = Mesh {
+        vertices: vec![
+            Vec3::new(-20.0, -20.0, 90.0),
+            Vec3::new(20.0, -20.0, 90.0),
+            Vec3::new(20.0, 20.0, 90.0),
+            Vec3::new(-20.0, 20.0, 90.0),
+        ],
+        indices: vec![0, 1, 2, 0, 2, 3],
+        uvs: Some(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ]),
+        ..Mesh::default()
+    };
+    let mut plane = Object::new(Rc::new(mesh));
+    plane.material.texture = Some(Arc::new(checkerboard(64)));
+    // Every mode reads from a coarser level, so the checkerboard averages out to gray
+    for filter in [Filter::Nearest, Filter::Bilinear, Filter::Trilinear] {
+        canvas.clear(Color::RED);
+        depth.clear();
+        plane.material.filter = filter;
+        plane.render_solid(&mut canvas, &mut depth, &camera, &[]);
+        for x in 42..58 {
+            let shade = canvas.get_pixel(Point::new(x, 50)).unwrap().r;
+            assert!((100..156).contains(&shade));
+        }
+    }
+}
+
+#[test]
+fn light_intensity() {
+    let up = -Vec3::Y;
+    let ambient = Light::Ambient { intensity: 0.25 };
+    assert_eq!(ambient.intensity_at(Vec3::ZERO, up), 0.25);
+    let sun = Light::Directional {
+        dir: Vec3::new(1.0, 1.0, 0.0),
+        intensity: 1.0,
+    };
+    assert!((sun.intensity_at(Vec3::ZERO, up) - FRAC_1_SQRT_2).abs() < 1e-9);
+    assert_eq!(sun.intensity_at(Vec3::ZERO, Vec3::Y), 0.0);
+
+    let bulb = Light::Point {
+        pos: Vec3::new(0.0, -2.0, 0.0),
+        intensity: 1.0,
+        attenuation: Attenuation {
+            quadratic: 1.0,
+            ..Attenuation::NONE
+        },
+    };
+    assert!((bulb.intensity_at(Vec3::ZERO, up) - 0.2).abs() < 1e-9);
+
+    let spot = |x: FP| {
+        Light::Spot {
+            pos: Vec3::new(0.0, -1.0, 0.0),
+            dir: Vec3::Y,
+            inner: 0.25 * FRAC_PI_2,
+            outer: 0.75 * FRAC_PI_2,
+            intensity: 1.0,
+            attenuation: Attenuation::NONE,
+        }
+        .intensity_at(Vec3::new(x, 0.0, 0.0), up)
+    };
+    assert!((spot(0.0) - 1.0).abs() < 1e-9);
+    assert_eq!(spot(10.0), 0.0);
+    // 45 degrees off axis is halfway through the falloff
+    assert!((spot(1.0) - 0.5 * FRAC_1_SQRT_2).abs() < 1e-9);
+
+    assert_eq!(illuminate(&[ambient, sun, bulb], Vec3::ZERO, up), 1.0);
+    assert_eq!(illuminate(&[], Vec3::ZERO, up), 0.0);
+}
+
+#[test]
+fn lit_cube() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let mut cube = Object {
+        cull: CullMode::Back,
+        ..Object::new(Rc::new(cube()))
+    };
+    let ambient = Light::Ambient { intensity: 0.2 };
+    let light = |dir: Vec3| {
+        [
+            ambient,
+            Light::Directional {
+                dir,
+                intensity: 0.8,
+            },
+        ]
+    };
+    let mut shade = |cube: &Object, lights: &[Light]| {
+        canvas.clear(Color::BLACK);
+        depth.clear();
+        cube.render_solid(&mut canvas, &mut depth, &camera, lights);
+        canvas.get_pixel(Point::new(50, 50)).unwrap().r
+    };
+    // Only the face towards the camera is visible
+    assert_eq!(shade(&cube, &light(Vec3::Z)), 255);
+    assert_eq!(shade(&cube, &light(-Vec3::Z)), 51);
+    // Smooth normals of a cube point out of its corners, so a light
+    // straight on the front face leaves it at 1/sqrt(3) everywhere
+    cube.shading = Shading::Gouraud;
+    let h = 0.2 + 0.8 / (3.0 as FP).sqrt();
+    assert_eq!(shade(&cube, &light(Vec3::Z)), (255.0 * h) as u8);
+    assert_eq!(shade(&cube, &light(-Vec3::Z)), 51);
+}
+
+#[test]
+fn mirrored_lighting() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let lights = [
+        Light::Ambient { intensity: 0.2 },
+        Light::Directional {
+            dir: Vec3::new(0.5, 0.0, 1.0),
+            intensity: 0.8,
+        },
+    ];
+    let mut center = |cube: &Object| {
+        canvas.clear(Color::RED);
+        depth.clear();
+        cube.render_solid(&mut canvas, &mut depth, &camera, &lights);
+        canvas.get_pixel(Point::new(50, 50)).unwrap()
+    };
+    let cube = |scale: Vec3, shading| Object {
+        cull: CullMode::Back,
+        shading,
+        ..Object::from_matrix(Rc::new(cube()), Mat4::from_scale(plain(scale)))
+    };
+    // Mirroring a cube without normals along x leaves it looking the same from the front
+    for shading in [Shading::Flat, Shading::Gouraud, Shading::Phong] {
+        let plain_cube = center(&cube(Vec3::splat(2.0), shading));
+        assert!(plain_cube.r > 51, "{:?}", shading);
+        assert_eq!(
+            center(&cube(Vec3::new(-2.0, 2.0, 2.0), shading)),
+            plain_cube,
+            "{:?}",
+            shading
+        );
+    }
+}
+
+#[test]
+fn two_sided_lighting() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let quad = |indices| Mesh {
+        vertices: vec![
+            Vec3::new(-5.0, -5.0, 0.0),
+            Vec3::new(5.0, -5.0, 0.0),
+            Vec3::new(5.0, 5.0, 0.0),
+            Vec3::new(-5.0, 5.0, 0.0),
+        ],
+        indices,
+        ..Mesh::default()
+    };
+    let front = Rc::new(quad(vec![0, 2, 1, 0, 3, 2]));
+    let back = Rc::new(quad(vec![0, 1, 2, 0, 2, 3]));
+    let lights = [Light::Directional {
+        dir: Vec3::Z,
+        intensity: 1.0,
+    }];
+    let mut center = |quad: &Object| {
+        canvas.clear(Color::RED);
+        depth.clear();
+        quad.render_solid(&mut canvas, &mut depth, &camera, &lights);
+        canvas.get_pixel(Point::new(50, 50)).unwrap()
+    };
+    for shading in [Shading::Flat, Shading::Gouraud, Shading::Phong] {
+        let object = |mesh: &Rc<Mesh>, cull| Object {
+            shading,
+            cull,
+            ..Object::new(mesh.clone())
+        };
+        assert_eq!(center(&object(&front, CullMode::None)), Color::WHITE);
+        // Without culling the back of a face is lit just like the front
+        assert_eq!(center(&object(&back, CullMode::None)), Color::WHITE);
+        // Otherwise it stays dark if it gets drawn at all
+        assert_eq!(center(&object(&back, CullMode::Front)), Color::BLACK);
+    }
+}
+
+#[test]
+fn blinn_phong_terms() {
+    let normal = -Vec3::Z;
+    let to_eye = -Vec3::Z;
+    let lights = [
+        Light::Ambient { intensity: 0.1 },
+        Light::Directional {
+            dir: Vec3::Z,
+            intensity: 0.5,
+        },
+    ];
+    let lighting = blinn_phong(&lights, Vec3::ZERO, normal, to_eye, 16.0);
+    assert!((lighting.diffuse - 0.6).abs() < 1e-9);
+    assert!((lighting.specular - 0.5).abs() < 1e-9);
+    // Eye off to the side puts the halfway vector 45 degrees off the normal
+    let side = blinn_phong(&lights[1..], Vec3::ZERO, normal, Vec3::X, 16.0);
+    assert!((side.specular - 0.5 * FRAC_1_SQRT_2.powi(16)).abs() < 1e-9);
+    // Lights behind the surface give no highlight
+    let behind = blinn_phong(&lights[1..], Vec3::ZERO, -normal, -normal, 16.0);
+    assert_eq!(behind, Lighting::default());
+}
+
+#[test]
+fn phong_highlight() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    // Quad much larger than the highlight, the corners barely get any of it
+    let mesh = Mesh {
+        vertices: vec![
+            Vec3::new(-10.0, -10.0, 0.0),
+            Vec3::new(10.0, -10.0, 0.0),
+            Vec3::new(10.0, 10.0, 0.0),
+            Vec3::new(-10.0, 10.0, 0.0),
+        ],
+        indices: vec![0, 2, 1, 0, 3, 2],
+        normals: Some(vec![-Vec3::Z; 4]),
+        ..Mesh::default()
+    };
+    let mut quad = Object::new(Rc::new(mesh));
+    quad.material = Material {
+        color: Color::RGB(0, 0, 255),
+        diffuse: 0.5,
+        specular: 1.0,
+        shininess: 64.0,
+        ..Material::default()
+    };
+    let lights = [Light::Point {
+        pos: camera.pos,
+        intensity: 1.0,
+        attenuation: Attenuation::NONE,
+    }];
+    let mut center = |quad: &Object| {
+        canvas.clear(Color::BLACK);
+        depth.clear();
+        quad.render_solid(&mut canvas, &mut depth, &camera, &lights);
+        canvas.get_pixel(Point::new(50, 50)).unwrap()
+    };
+    quad.shading = Shading::Phong;
+    // The pixel center is half a pixel off the peak of the highlight
+    let phong = center(&quad);
+    assert!(phong.r > 250 && phong.g > 250 && phong.b > 250);
+    // Gouraud only has the dimmer corners to interpolate between
+    quad.shading = Shading::Gouraud;
+    let gouraud = center(&quad);
+    assert_eq!(gouraud.r, 0);
+    assert!(gouraud.b < 128);
+}
+
+// Colors interpolated from the vertices, with everything left of the middle discarded
+struct VertexColors<'a> {
+    camera: &'a Camera,
+}
+
+impl VertexShader for VertexColors<'_> {
+    type Input = (Vec3, Vec3);
+    type Varying = Vec3;
+    fn shade(&self, input: &(Vec3, Vec3)) -> (Vec4, Vec3) {
+        (to_clip(self.camera, &input.0), input.1)
+    }
+}
+
+#[test]
+fn custom_shaders() {
+    let mut canvas = Framebuffer::new(100, 100);
+    canvas.clear(Color::BLACK);
+    let mut depth = DepthBuffer::new(100, 100);
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let inputs = [
+        (Vec3::new(-5.0, -5.0, 0.0), Vec3::new(255.0, 0.0, 0.0)),
+        (Vec3::new(5.0, -5.0, 0.0), Vec3::new(0.0, 0.0, 255.0)),
+        (Vec3::new(5.0, 5.0, 0.0), Vec3::new(0.0, 0.0, 255.0)),
+        (Vec3::new(-5.0, 5.0, 0.0), Vec3::new(255.0, 0.0, 0.0)),
+    ];
+    let fragment = |f: &Fragment<Vec3>| match f.p.x < 50 {
+        true => None,
+        false => Some(Color::RGB(f.varying.x as u8, 0, f.varying.z as u8)),
+    };
+    let pipeline = Pipeline {
+        cull: CullMode::Back,
+        ..Pipeline::new(VertexColors { camera: &camera }, fragment)
+    };
+    // The second triangle faces away
+    let triangles = vec![[0, 3, 2], [0, 1, 2], [0, 2, 1]].into_iter();
+    let stats = pipeline.draw(&mut canvas, Some(&mut depth), &camera, &inputs, triangles);
+    assert_eq!(
+        stats,
+        RenderStats {
+            drawn: 2,
+            culled: 1
+        }
+    );
+
+    let left = Point::new(40, 50);
+    assert_eq!(canvas.get_pixel(left), Some(Color::BLACK));
+    assert!(depth.test(left, FP::MIN_POSITIVE));
+    let right = canvas.get_pixel(Point::new(70, 50)).unwrap();
+    assert!(right.b > right.r && right.r > 0);
+    assert!(!depth.test(Point::new(70, 50), 0.05));
+}
+
+// How many times each pixel gets drawn by a set of triangles
+fn coverage(triangles: &[[(FP, FP); 3]]) -> Vec<u32> {
+    let mut canvas = Framebuffer::new(32, 32);
+    let hits = RefCell::new(vec![0; 32 * 32]);
+    let count = |f: &Fragment<()>| {
+        hits.borrow_mut()[(f.p.y * 32 + f.p.x) as usize] += 1;
+        None
+    };
+    for tri in triangles {
+        let [a, b, c] = tri.map(|(x, y)| Xyzv::new(x, y, 1.0, ()));
+        draw_varying_triangle(&mut canvas, None, a, b, c, &count);
+    }
+    hits.into_inner()
+}
+
+#[test]
+fn watertight_edges() {
+    // Square with its sides and diagonal going right through pixel centers,
+    // split both ways and with both windings
+    let (tl, tr, br, bl) = ((4.5, 4.5), (20.5, 4.5), (20.5, 20.5), (4.5, 20.5));
+    for split in [
+        [[tl, tr, br], [tl, br, bl]],
+        [[tl, br, tr], [bl, br, tl]],
+        [[tl, tr, bl], [tr, br, bl]],
+    ] {
+        let hits = coverage(&split);
+        // Pixels whose center is on the top or left side belong to the square
+        for y in 0..32 {
+            for x in 0..32 {
+                let inside = (4..20).contains(&x) && (4..20).contains(&y);
+                assert_eq!(hits[y * 32 + x], inside as u32, "pixel {} {}", x, y);
+            }
+        }
+    }
+
+    // Fan around a point off the pixel grid, with corners at sub-pixel positions
+    // so that the shared edges go every direction
+    let center = (15.3, 16.7);
+    let corners = [
+        (2.25, 1.5),
+        (16.0, 3.0),
+        (29.7, 2.1),
+        (30.2, 16.5),
+        (28.9, 30.6),
+        (15.5, 29.0),
+        (1.1, 30.3),
+        (3.0, 16.0),
+    ];
+    let fan: Vec<_> = (0..corners.len())
+        .map(|idx| [center, corners[idx], corners[(idx + 1) % corners.len()]])
+        .collect();
+    let hits = coverage(&fan);
+    assert!(hits.iter().all(|&h| h <= 1));
+    // Everything well inside the outline is covered without gaps
+    for y in 6..26 {
+        for x in 6..26 {
+            assert_eq!(hits[y * 32 + x], 1, "pixel {} {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn tiled_matches_single_threaded() {
+    // Not a whole number of tiles, with shading modes that use every kind of fragment shader
+    let (width, height) = (150, 110);
+    let camera = Camera::new(
+        Some(Vec3::new(0.0, 0.0, -10.0)),
+        None,
+        None,
+        (width, height),
+    );
+    let teapot = Rc::new(teapot());
+    let floor = Mesh {
+        vertices: vec![
+            Vec3::new(-8.0, 2.0, -2.0),
+            Vec3::new(8.0, 2.0, -2.0),
+            Vec3::new(8.0, 2.0, 12.0),
+            Vec3::new(-8.0, 2.0, 12.0),
+        ],
+        indices: vec![0, 1, 2, 0, 2, 3],
+        uvs: Some(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(4.0, 0.0),
+            Vec2::new(4.0, 4.0),
+            Vec2::new(0.0, 4.0),
+        ]),
+        ..Mesh::default()
+    };
+    let objects = vec![
+        Object {
+            transform: affine(Vec3::splat(3.0), Quat::IDENTITY, Vec3::new(-2.0, 2.0, 0.0)),
+            shading: Shading::Phong,
+            ..Object::new(teapot.clone())
+        },
+        Object {
+            transform: affine(Vec3::splat(3.0), Quat::IDENTITY, Vec3::new(2.0, 2.0, 1.0)),
+            shading: Shading::Gouraud,
+            cull: CullMode::Back,
+            ..Object::new(teapot)
+        },
+        Object {
+            material: Material {
+                texture: Some(Arc::new(checkerboard(16))),
+                ..Material::default()
+            },
+            ..Object::new(Rc::new(floor))
+        },
+    ];
+    let mut scene = Scene {
+        camera,
+        objects,
+        lights: Vec::new(),
+        threads: 1,
+    };
+    let render = |scene: &Scene, tiled: bool| {
+        let mut canvas = Framebuffer::new(width, height);
+        canvas.clear(Color::WHITE);
+        let mut depth = DepthBuffer::new(width, height);
+        let stats = match tiled {
+            true => scene.render_tiled(&mut canvas, &mut depth),
+            false => scene.render_solid(&mut canvas, &mut depth),
+        };
+        (canvas.pixels().to_vec(), depth.values().to_vec(), stats)
+    };
+    let expected = render(&scene, false);
+    assert!(expected.2.drawn > 0);
+    for threads in [1, 3] {
+        scene.threads = threads;
+        assert!(render(&scene, true) == expected, "{} threads", threads);
+    }
+}
+
+#[test]
+fn soa_transform() {
+    let camera = Camera::new(
+        Some(Vec3::new(1.0, -2.0, -10.0)),
+        Some(Quat::from_rotation_x(0.3)),
+        None,
+        (800, 600),
+    );
+    // Not a whole number of lanes
+    let points: Vec<Vec3> = (0..7)
+        .map(|idx| Vec3::new(idx as FP, 2.0 - idx as FP, 0.5 * idx as FP))
+        .collect();
+    let model = affine(
+        Vec3::splat(2.5),
+        Quat::from_rotation_y(1.0),
+        Vec3::new(0.0, 3.0, 1.0),
+    );
+    let soa = crate::soa::SoaPoints::new(&points).transform(&Mat4::from(model));
+    assert_eq!(soa.len(), 7);
+    let world = soa.to_points();
+    let clip = soa.to_clip(&camera);
+    assert_eq!((world.len(), clip.len()), (7, 7));
+    // Rounding differs from doing it one point at a time
+    let eps = FP::EPSILON * 1000.0;
+    for (idx, p) in points.iter().enumerate() {
+        let expected = transform_point(&model, *p);
+        assert!(world[idx].abs_diff_eq(expected, eps));
+        assert!(clip[idx].abs_diff_eq(to_clip(&camera, &expected), eps));
+    }
+}
+
+#[test]
+fn projections() {
+    let ndc = |m: Mat4, x: FP, y: FP, z: FP| {
+        let v = m * Vec4::new(x, y, z, 1.0);
+        Vec3::new(v.x / v.w, v.y / v.w, v.z / v.w)
+    };
+    let close = |a: Vec3, b: Vec3| a.abs_diff_eq(b, 1e-5);
+    // Wider than high, so the field of view is across x
+    let res = (200, 100);
+    let perspective = Projection::Perspective {
+        fov: FRAC_PI_2,
+        near: 1.0,
+        far: 10.0,
+    }
+    .matrix(res);
+    assert!(close(
+        ndc(perspective, -2.0, 1.0, 2.0),
+        Vec3::new(-1.0, 1.0, 5.0 / 9.0)
+    ));
+    assert!(close(ndc(perspective, 0.0, 0.0, 1.0), Vec3::ZERO));
+    assert!(close(ndc(perspective, 0.0, 0.0, 10.0), Vec3::Z));
+    assert_eq!((perspective * Vec4::new(0.0, 0.0, 3.0, 1.0)).w, 3.0);
+
+    let orthographic = Projection::Orthographic {
+        size: 8.0,
+        near: 0.0,
+        far: 4.0,
+    }
+    .matrix(res);
+    assert!(close(
+        ndc(orthographic, 4.0, -2.0, 2.0),
+        Vec3::new(1.0, -1.0, 0.5)
+    ));
+    assert_eq!((orthographic * Vec4::new(1.0, 2.0, 3.0, 1.0)).w, 1.0);
+
+    // Off center, and stretched to the screen
+    let frustum = Projection::Frustum {
+        min: Vec2::new(0.0, -1.0),
+        max: Vec2::new(2.0, 1.0),
+        near: 1.0,
+        far: FP::INFINITY,
+    }
+    .matrix(res);
+    assert!(close(
+        ndc(frustum, 0.0, -1.0, 1.0),
+        Vec3::new(-1.0, -1.0, 0.0)
+    ));
+    assert!(close(ndc(frustum, 4.0, 2.0, 2.0), Vec3::new(1.0, 1.0, 0.5)));
+    assert!(close(ndc(frustum, 2e6, 0.0, 1e6), Vec3::new(1.0, 0.0, 1.0)));
+}
+
+#[test]
+fn orthographic_depth() {
+    let mut canvas = Framebuffer::new(100, 100);
+    let mut depth = DepthBuffer::new(100, 100);
+    let mut camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    camera.projection = Projection::Orthographic {
+        size: 10.0,
+        near: 1.0,
+        far: 20.0,
+    };
+    let quad = |z: FP, color: Color| {
+        let mesh = Mesh {
+            vertices: vec![
+                Vec3::new(-1.0, -1.0, z),
+                Vec3::new(1.0, -1.0, z),
+                Vec3::new(1.0, 1.0, z),
+                Vec3::new(-1.0, 1.0, z),
+            ],
+            indices: vec![0, 2, 1, 0, 3, 2],
+            ..Mesh::default()
+        };
+        Object {
+            material: Material {
+                color,
+                ..Material::default()
+            },
+            ..Object::new(Rc::new(mesh))
+        }
+    };
+    let near = quad(0.0, Color::RED);
+    // Stretched to the right edge of the screen
+    let far = Object {
+        transform: affine(Vec3::new(5.0, 1.0, 1.0), Quat::IDENTITY, Vec3::ZERO),
+        ..quad(2.0, Color::BLUE)
+    };
+    for order in [[&near, &far], [&far, &near]] {
+        canvas.clear(Color::BLACK);
+        depth.clear();
+        for obj in order {
+            obj.render_solid(&mut canvas, &mut depth, &camera, &[]);
+        }
+        let pixel = |x, y| canvas.get_pixel(Point::new(x, y)).unwrap();
+        assert!(pixel(50, 50).r > 0 && pixel(50, 50).b == 0);
+        assert!(pixel(61, 50).r == 0 && pixel(61, 50).b > 0);
+        assert!(pixel(98, 50).b > 0);
+        // Without perspective things don't get smaller further away
+        assert!(pixel(50, 41).r > 0 && pixel(61, 41).b > 0);
+        assert_eq!(pixel(61, 62), Color::BLACK);
+    }
+}
+
+#[test]
+fn non_uniform_normals() {
+    // The plane x + y = 0 stretched along x turns towards x
+    let transform = affine(Vec3::new(2.0, 1.0, 1.0), Quat::IDENTITY, Vec3::ZERO);
+    let normal = transform_normal(&transform.inverse(), Vec3::new(1.0, 1.0, 0.0));
+    let expected = Vec3::new(0.5, 1.0, 0.0).normalize();
+    assert!(normal.normalize().abs_diff_eq(expected, 1e-6));
+    // Still perpendicular to the stretched plane
+    let along = transform_vector(&transform, Vec3::new(1.0, -1.0, 0.0));
+    assert!(normal.dot(along).abs() < 1e-6);
+}
+
+#[test]
+fn viewport_offsets() {
+    let mut canvas = Framebuffer::new(10, 10);
+    let mut viewport = Viewport::new(&mut canvas, Rect::new(5, 2, 3, 4));
+    assert_eq!(viewport.size(), (3, 4));
+    viewport.clear(Color::RED);
+    viewport.put_pixel(Point::new(0, 0), Color::BLUE);
+    viewport.put_pixel(Point::new(3, 0), Color::BLUE);
+    viewport.put_pixel(Point::new(-1, 1), Color::BLUE);
+    assert_eq!(canvas.get_pixel(Point::new(5, 2)), Some(Color::BLUE));
+    assert_eq!(canvas.get_pixel(Point::new(7, 5)), Some(Color::RED));
+    let touched = canvas.pixels().iter().filter(|&&px| px != 0).count();
+    assert_eq!(touched, 12);
+}
+
+#[test]
+fn multi_view() {
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (101, 51));
+    let mut views = MultiView::new(camera, (101, 51));
+    let kinds: Vec<ViewKind> = views.views.iter().map(|view| view.kind).collect();
+    assert_eq!(kinds, ViewKind::LAYOUT);
+    // Every pixel of the window belongs to exactly one view
+    let area: u32 = views
+        .views
+        .iter()
+        .map(|v| v.rect.width() * v.rect.height())
+        .sum();
+    assert_eq!(area, 101 * 51);
+    assert_eq!(views.view_at(Point::new(0, 0)), Some(0));
+    assert_eq!(views.view_at(Point::new(100, 50)), Some(3));
+    for view in &views.views {
+        assert_eq!(view.camera.res(), view.rect.size());
+    }
+
+    // Each orthographic view looks through the middle of the box from its own side
+    views.frame(Vec3::new(1.0, 1.0, 1.0), Vec3::new(3.0, 5.0, 3.0));
+    let center = Vec3::new(2.0, 3.0, 2.0);
+    let forwards = [Vec3::Y, Vec3::Z, Vec3::Z, -Vec3::X];
+    for (view, forward) in views.views.iter().zip(forwards) {
+        let camera = &view.camera;
+        assert!((camera.rot.conjugate() * Vec3::Z).abs_diff_eq(forward, 1e-6));
+        if view.kind == ViewKind::Perspective {
+            assert_eq!(camera.pos, Vec3::new(0.0, 0.0, -10.0));
+            continue;
+        }
+        let ndc = camera.view_projection() * center.extend(1.0);
+        assert!(aligned(ndc.truncate()).abs_diff_eq(Vec3::new(0.0, 0.0, 0.5), 1e-6));
+        // Far ends of the box stay on screen
+        for corner in [Vec3::new(1.0, 1.0, 1.0), Vec3::new(3.0, 5.0, 3.0)] {
+            let ndc = camera.view_projection() * corner.extend(1.0);
+            assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0);
+            assert!((0.0..=1.0).contains(&ndc.z));
+        }
+    }
+    // Further things end up higher up in the top view
+    let top = views.views[0].camera.view_projection();
+    assert!((top * Vec4::new(2.0, 3.0, 3.0, 1.0)).y < 0.0);
+    // Zooming only changes the orthographic views
+    let right = Vec4::new(3.0, 3.0, 2.0, 1.0);
+    views.views[0].camera.zoom(2.0);
+    views.views[1].camera.zoom(2.0);
+    let zoomed = views.views[0].camera.view_projection();
+    assert!(((top * right).x - 2.0 * (zoomed * right).x).abs() < 1e-6);
+    assert_eq!(views.views[1].camera.fov(), Some(90));
+}
+
+#[test]
+fn multi_view_controls() {
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (100, 100));
+    let mut views = MultiView::new(camera, (100, 100));
+    let keys = HeldKeys {
+        movement: Vec3::new(1.0, 0.0, 1.0),
+        turn: Vec2::new(1.0, 1.0),
+        sprint: false,
+    };
+
+    // The perspective view turns without rolling
+    views.active = 1;
+    for _ in 0..20 {
+        views.update(&keys, 0.1);
+        let camera = &views.views[1].camera;
+        assert!((camera.rot.conjugate() * Vec3::X).y.abs() < 1e-6);
+    }
+
+    // The front view pans right and zooms in, but keeps looking the same way from the same depth
+    views.active = 2;
+    let before = views.views[2].camera;
+    let size = |camera: &Camera| match camera.projection {
+        Projection::Orthographic { size, .. } => size,
+        _ => unreachable!(),
+    };
+    for _ in 0..10 {
+        views.update(&keys, 0.1);
+    }
+    let after = &views.views[2].camera;
+    assert_eq!(after.rot, before.rot);
+    assert!(after.pos.x > before.pos.x);
+    assert!((after.pos.y - before.pos.y).abs() < 1e-6);
+    assert!((after.pos.z - before.pos.z).abs() < 1e-6);
+    assert!(size(after) < size(&before));
+}
+
+fn key_down(keycode: Keycode) -> Event {
+    Event::KeyDown {
+        timestamp: 0,
+        window_id: 0,
+        keycode: Some(keycode),
+        scancode: None,
+        keymod: Mod::NOMOD,
+        repeat: false,
+    }
+}
+
+fn mouse_motion(xrel: i32, yrel: i32) -> Event {
+    Event::MouseMotion {
+        timestamp: 0,
+        window_id: 0,
+        which: 0,
+        mousestate: MouseState::from_sdl_state(0),
+        x: 0,
+        y: 0,
+        xrel,
+        yrel,
+    }
+}
+
+// Mouse motion with buttons held down, `buttons` has a bit set for each starting with the left one
+fn mouse_drag(buttons: u32, xrel: i32, yrel: i32) -> Event {
+    let mut event = mouse_motion(xrel, yrel);
+    if let Event::MouseMotion { mousestate, .. } = &mut event {
+        *mousestate = MouseState::from_sdl_state(buttons);
+    }
+    event
+}
+
+#[test]
+fn first_person_look() {
+    // Rolled cameras are straightened out
+    let rot = Quat::from_rotation_x(0.3) * Quat::from_rotation_y(1.0) * Quat::from_rotation_z(0.5);
+    let mut camera = Camera::new(None, Some(rot), None, (100, 100));
+    let forward = camera.rot.conjugate() * Vec3::Z;
+    let mut first_person = FirstPerson::new(&camera);
+    assert!((first_person.rotation().conjugate() * Vec3::Z).abs_diff_eq(forward, 1e-6));
+
+    // The mouse only looks around while grabbed
+    first_person.handle_event(&mut camera, &mouse_motion(50, 50));
+    assert!((camera.rot.conjugate() * Vec3::Z).abs_diff_eq(forward, 1e-6));
+    first_person.handle_event(&mut camera, &key_down(Keycode::Tab));
+    assert!(first_person.grabbed());
+    first_person.sensitivity = 0.01;
+    let turn_left = HeldKeys {
+        turn: Vec2::new(-1.0, 0.0),
+        ..HeldKeys::default()
+    };
+    for _ in 0..100 {
+        first_person.handle_event(&mut camera, &mouse_motion(7, -3));
+        first_person.update(&mut camera, &turn_left, 0.01);
+        // Right stays level, however much the camera turned
+        assert!((camera.rot.conjugate() * Vec3::X).y.abs() < 1e-6);
+    }
+    // Pitch stops at looking straight up
+    assert_eq!(first_person.pitch, -FirstPerson::MAX_PITCH);
+    assert!((camera.rot.conjugate() * Vec3::Z).abs_diff_eq(-Vec3::Y, 1e-6));
+
+    // Moving the mouse right turns right
+    first_person.pitch = 0.0;
+    first_person.yaw = 0.0;
+    first_person.handle_event(&mut camera, &mouse_motion(10, 0));
+    assert!((camera.rot.conjugate() * Vec3::Z).x > 0.0);
+    first_person.handle_event(&mut camera, &key_down(Keycode::Tab));
+    assert!(!first_person.grabbed());
+}
+
+#[test]
+fn orbit() {
+    let camera = Camera::new(Some(Vec3::new(0.0, 0.0, -10.0)), None, None, (200, 100));
+    let mut orbit = Orbit::new(&camera, Vec3::new(0.0, 0.0, 10.0));
+    assert_eq!((orbit.distance, orbit.yaw, orbit.pitch), (20.0, 0.0, 0.0));
+    let mut scene = Scene {
+        camera,
+        objects: vec![Object {
+            transform: affine(Vec3::new(1.0, 2.0, 3.0), Quat::IDENTITY, Vec3::splat(5.0)),
+            ..Object::new(Rc::new(cube()))
+        }],
+        lights: vec![],
+        threads: 0,
+    };
+    let screen = |camera: &Camera, p: Vec3| {
+        let v = camera.view_projection() * p.extend(1.0);
+        Vec3::new(v.x / v.w, v.y / v.w, v.z / v.w)
+    };
+    let close = |a: Vec3, b: Vec3| a.abs_diff_eq(b, 1e-5);
+
+    // Rotating keeps the target in the middle at the same distance, dragging up moves the camera
+    // below the target since y is down
+    orbit.handle_event(&mut scene, &mouse_drag(1, 40, -25));
+    let camera = &scene.camera;
+    let middle = screen(camera, orbit.target);
+    assert!(middle.x.abs() < 1e-5 && middle.y.abs() < 1e-5);
+    assert!(((camera.pos - orbit.target).length() - 20.0).abs() < 1e-5);
+    assert!((camera.rot.conjugate() * Vec3::X).y.abs() < 1e-6);
+    assert!(camera.pos.x < 0.0 && camera.pos.y > 0.0);
+
+    // Panning drags what is under the mouse along with it, 1 pixel is 0.01 across
+    let before = orbit.target;
+    orbit.handle_event(&mut scene, &mouse_drag(4, 10, 0));
+    let moved = screen(&scene.camera, before);
+    assert!((moved.x - 0.1).abs() < 1e-5 && moved.y.abs() < 1e-5);
+
+    let wheel = Event::MouseWheel {
+        timestamp: 0,
+        window_id: 0,
+        which: 0,
+        x: 0,
+        y: 2,
+        direction: MouseWheelDirection::Normal,
+    };
+    orbit.handle_event(&mut scene, &wheel);
+    assert!((orbit.distance - 20.0 * 0.81).abs() < 1e-5);
+    // Dollying in stops just short of the near plane reaching the target
+    for _ in 0..50 {
+        orbit.handle_event(&mut scene, &wheel);
+    }
+    assert!(orbit.distance > 1.0 && orbit.distance < 1.1);
+
+    // Framing fits the sphere around the object, which leaves it a good part of the screen
+    orbit.handle_event(&mut scene, &key_down(Keycode::F));
+    assert!(close(orbit.target, Vec3::splat(5.0)));
+    let corners = || {
+        (0..8).map(|idx| {
+            let pick = |bit: u32, size: FP| match idx >> bit & 1 {
+                0 => 5.0 - size / 2.0,
+                _ => 5.0 + size / 2.0,
+            };
+            Vec3::new(pick(0, 1.0), pick(1, 2.0), pick(2, 3.0))
+        })
+    };
+    let extent = |camera: &Camera| {
+        corners()
+            .map(|corner| screen(camera, corner))
+            .inspect(|p| assert!(p.x.abs() <= 1.0 && p.y.abs() <= 1.0 && p.z > 0.0))
+            .fold(0.0 as FP, |extent, p| extent.max(p.y.abs()))
+    };
+    assert!(extent(&scene.camera) > 0.3);
+
+    // Same for orthographic cameras
+    scene.camera.projection = Projection::Orthographic {
+        size: 1.0,
+        near: 0.0,
+        far: 1.0,
+    };
+    orbit.handle_event(&mut scene, &key_down(Keycode::F));
+    assert!(extent(&scene.camera) > 0.3);
+    assert!(corners().all(|corner| screen(&scene.camera, corner).z < 1.0));
+}
+
+#[test]
+fn held_key_movement() {
+    let forward = HeldKeys {
+        movement: Vec3::Z,
+        ..HeldKeys::default()
+    };
+    // Distance covered in `seconds` at `fps` with `keys` held from standing still
+    let travel = |keys: &HeldKeys, fps: u32, seconds: FP| {
+        let mut movement = Movement::new();
+        let frames = (seconds * fps as FP).round() as u32;
+        (0..frames).fold(Vec3::ZERO, |pos, _| {
+            pos + movement.step(keys, 1.0 / fps as FP)
+        })
+    };
+    // Speeding up takes 0.1s, after which it goes at 3 units per second
+    let expected = 0.5 * 0.1 * 3.0 + 0.9 * 3.0;
+    for fps in [5, 30, 60, 144, 300] {
+        let moved = travel(&forward, fps, 1.0);
+        assert!(
+            (moved.z - expected).abs() < 1e-3,
+            "{} at {} FPS",
+            moved.z,
+            fps
+        );
+    }
+
+    // Diagonals are as fast as going straight, sprinting is faster
+    let diagonal = HeldKeys {
+        movement: Vec3::new(1.0, 0.0, 1.0),
+        ..HeldKeys::default()
+    };
+    assert!((travel(&diagonal, 60, 1.0).length() - expected).abs() < 1e-3);
+    let sprint = HeldKeys {
+        sprint: true,
+        ..forward
+    };
+    assert!((travel(&sprint, 60, 1.0).z - (0.5 * 0.3 * 9.0 + 0.7 * 9.0)).abs() < 1e-3);
+
+    // Letting go slows down to a stop
+    let mut movement = Movement::new();
+    movement.step(&forward, 1.0);
+    let coasted = movement.step(&HeldKeys::default(), 1.0);
+    assert!((coasted.z - 0.15).abs() < 1e-6);
+    assert_eq!(movement.velocity(), Vec3::ZERO);
+    movement.acceleration = FP::INFINITY;
+    assert_eq!(movement.step(&forward, 0.5), Vec3::new(0.0, 0.0, 1.5));
+    let turn_right = HeldKeys {
+        turn: Vec2::new(1.0, 0.0),
+        ..HeldKeys::default()
+    };
+    assert!((movement.turn(&turn_right, 0.5).x - 0.75).abs() < 1e-6);
+
+    // First person movement stays level when looking down, Space goes up
+    let mut camera = Camera::new(None, Some(Quat::from_rotation_x(0.5)), None, (100, 100));
+    let mut first_person = FirstPerson::new(&camera);
+    first_person.update(&mut camera, &forward, 1.0);
+    assert!(camera.pos.y.abs() < 1e-6 && camera.pos.z > 0.0);
+    let up = HeldKeys {
+        movement: -Vec3::Y,
+        ..HeldKeys::default()
+    };
+    first_person.update(&mut camera, &up, 1.0);
+    assert!(camera.pos.y < 0.0);
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use sdl2::pixels::Color;
+
+use crate::math::*;
+use crate::meshes::LoadError;
+
+// Nearest and Bilinear read from the closest mip level, Trilinear blends the two closest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i32, y: i32) -> Color {
+        let x = x.rem_euclid(self.width as i32) as usize;
+        let y = y.rem_euclid(self.height as i32) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+    fn sample(&self, uv: Vec2, bilinear: bool) -> Color {
+        // Texel centers are at half coordinates
+        let pos = uv * Vec2::new(self.width as FP, self.height as FP);
+        match bilinear {
+            false => self.texel(pos.x.floor() as i32, pos.y.floor() as i32),
+            true => {
+                let pos = pos - 0.5;
+                let base = pos.floor();
+                let t = pos - base;
+                let (x, y) = (base.x as i32, base.y as i32);
+                let top = lerp_color(self.texel(x, y), self.texel(x + 1, y), t.x);
+                let bottom = lerp_color(self.texel(x, y + 1), self.texel(x + 1, y + 1), t.x);
+                lerp_color(top, bottom, t.y)
+            }
+        }
+    }
+    // Averages 2x2 blocks, the last row or column is reused when the size is odd
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let (x0, y0) = (x * 2, y * 2);
+                let x1 = (x0 + 1).min(self.width as i32 - 1);
+                let y1 = (y0 + 1).min(self.height as i32 - 1);
+                let block = [
+                    self.texel(x0, y0),
+                    self.texel(x1, y0),
+                    self.texel(x0, y1),
+                    self.texel(x1, y1),
+                ];
+                let channel = |f: fn(&Color) -> u8| {
+                    ((block.iter().map(|c| f(c) as u16).sum::<u16>() + 2) / 4) as u8
+                };
+                texels.push(Color::RGBA(
+                    channel(|c| c.r),
+                    channel(|c| c.g),
+                    channel(|c| c.b),
+                    channel(|c| c.a),
+                ));
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+// RGBA image, UV (0, 0) is the top left corner and coordinates outside of 0..1 repeat.
+// The whole mip chain down to 1x1 is generated on creation.
+pub struct Texture {
+    levels: Vec<MipLevel>,
+}
+
+impl Texture {
+    pub fn new(width: u32, height: u32, texels: Vec<Color>) -> Texture {
+        assert_eq!(texels.len(), (width * height) as usize);
+        assert!(width > 0 && height > 0);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            levels.push(last.downsample());
+        }
+        Texture { levels }
+    }
+    pub fn load(path: &Path) -> Result<Texture, LoadError> {
+        let image = image::open(path)?.to_rgba8();
+        let texels = image
+            .pixels()
+            .map(|p| Color::RGBA(p[0], p[1], p[2], p[3]))
+            .collect();
+        Ok(Texture::new(image.width(), image.height(), texels))
+    }
+    pub fn from_gltf(data: &gltf::image::Data) -> Result<Texture, LoadError> {
+        use gltf::image::Format;
+        let texels = match data.format {
+            Format::R8 => data.pixels.iter().map(|&l| Color::RGB(l, l, l)).collect(),
+            Format::R8G8 => data
+                .pixels
+                .chunks_exact(2)
+                .map(|p| Color::RGBA(p[0], p[0], p[0], p[1]))
+                .collect(),
+            Format::R8G8B8 => data
+                .pixels
+                .chunks_exact(3)
+                .map(|p| Color::RGB(p[0], p[1], p[2]))
+                .collect(),
+            Format::R8G8B8A8 => data
+                .pixels
+                .chunks_exact(4)
+                .map(|p| Color::RGBA(p[0], p[1], p[2], p[3]))
+                .collect(),
+            format => {
+                return Err(LoadError::Parse(format!(
+                    "unsupported texture format {:?}",
+                    format
+                )))
+            }
+        };
+        Ok(Texture::new(data.width, data.height, texels))
+    }
+    pub fn size(&self) -> (u32, u32) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+    pub fn mip_count(&self) -> usize {
+        self.levels.len()
+    }
+    pub fn mip_size(&self, level: usize) -> (u32, u32) {
+        (self.levels[level].width, self.levels[level].height)
+    }
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        self.levels[0].texel(x, y)
+    }
+    // Level of detail from the UV derivatives along screen x and y,
+    // 0 when one texel covers one pixel and 1 when two texels do
+    pub fn lod(&self, duv_dx: Vec2, duv_dy: Vec2) -> FP {
+        let (width, height) = self.size();
+        let scale = Vec2::new(width as FP, height as FP);
+        let footprint = (duv_dx * scale).length().max((duv_dy * scale).length());
+        footprint.log2()
+    }
+    // Samples the full resolution image
+    pub fn sample(&self, uv: Vec2, filter: Filter) -> Color {
+        self.sample_lod(uv, 0.0, filter)
+    }
+    pub fn sample_lod(&self, uv: Vec2, lod: FP, filter: Filter) -> Color {
+        let max_level = (self.levels.len() - 1) as FP;
+        // NaN from degenerate derivatives picks the full resolution level
+        let lod = match lod.is_nan() {
+            true => 0.0,
+            false => lod.clamp(0.0, max_level),
+        };
+        match filter {
+            Filter::Nearest => self.levels[lod.round() as usize].sample(uv, false),
+            Filter::Bilinear => self.levels[lod.round() as usize].sample(uv, true),
+            Filter::Trilinear => {
+                let level = lod.floor();
+                let fine = self.levels[level as usize].sample(uv, true);
+                if level == max_level {
+                    return fine;
+                }
+                let coarse = self.levels[level as usize + 1].sample(uv, true);
+                lerp_color(fine, coarse, lod - level)
+            }
+        }
+    }
+}
+
+pub fn lerp_color(a: Color, b: Color, t: FP) -> Color {
+    let channel = |a: u8, b: u8| (a as FP + (b as FP - a as FP) * t).round() as u8;
+    Color::RGBA(
+        channel(a.r, b.r),
+        channel(a.g, b.g),
+        channel(a.b, b.b),
+        channel(a.a, b.a),
+    )
+}
+
+// Componentwise multiplication, as if the colors were 0..1
+pub fn modulate(a: Color, b: Color) -> Color {
+    let channel = |a: u8, b: u8| ((a as u16 * b as u16 + 127) / 255) as u8;
+    Color::RGBA(
+        channel(a.r, b.r),
+        channel(a.g, b.g),
+        channel(a.b, b.b),
+        channel(a.a, b.a),
+    )
+}
@@ -0,0 +1,228 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use sdl2::rect::Point;
+
+use crate::math::*;
+use crate::pipeline::*;
+use crate::target::*;
+
+// Width and height of the square tiles the screen gets split into
+pub const TILE_SIZE: u32 = 64;
+
+// Triangles sharing a fragment shader, with their types erased so that all batches fit in one list
+trait Batch: Sync {
+    fn draw(&self, triangle: usize, tile: &mut Tile);
+}
+
+struct ShadedBatch<V, FS> {
+    fragment: FS,
+    triangles: Vec<[Xyzv<V>; 3]>,
+}
+
+impl<V: Lerp + Send + Sync, FS: FragmentShader<V> + Sync> Batch for ShadedBatch<V, FS> {
+    fn draw(&self, triangle: usize, tile: &mut Tile) {
+        let [p0, p1, p2] = self.triangles[triangle];
+        draw_varying_triangle_at(
+            &mut tile.color,
+            Some(&mut tile.depth),
+            tile.origin,
+            p0,
+            p1,
+            p2,
+            &self.fragment,
+        );
+    }
+}
+
+// One thread's copy of the part of the screen it is drawing
+struct Tile {
+    origin: Point,
+    color: Framebuffer,
+    depth: DepthBuffer,
+}
+
+// Copies a rectangle of `size` pixels between buffers with rows of different widths
+fn copy_rect<T: Copy>(
+    src: &[T],
+    src_width: u32,
+    src_at: Point,
+    dst: &mut [T],
+    dst_width: u32,
+    dst_at: Point,
+    size: (u32, u32),
+) {
+    let width = size.0 as usize;
+    let start = |at: Point, row_width: u32, row: usize| {
+        (at.y as usize + row) * row_width as usize + at.x as usize
+    };
+    for row in 0..size.1 as usize {
+        let (from, to) = (start(src_at, src_width, row), start(dst_at, dst_width, row));
+        dst[to..to + width].copy_from_slice(&src[from..from + width]);
+    }
+}
+
+// Sorts triangles into the screen tiles they touch, so that the tiles can be rasterized
+// in parallel later on. Every tile draws its triangles in the order they were submitted,
+// which gives the same picture as drawing them one after the other.
+pub struct Bins<'a> {
+    width: u32,
+    height: u32,
+    columns: u32,
+    rows: u32,
+    batches: Vec<Box<dyn Batch + 'a>>,
+    // Batch and triangle index of everything touching each tile, row-major
+    tiles: Vec<Vec<(usize, usize)>>,
+}
+
+impl<'a> Bins<'a> {
+    pub fn new(width: u32, height: u32) -> Bins<'a> {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+        Bins {
+            width,
+            height,
+            columns,
+            rows,
+            batches: Vec::new(),
+            tiles: vec![Vec::new(); (columns * rows) as usize],
+        }
+    }
+    // Draws every tile into the canvas and depth buffer, which have to be the size passed to new.
+    // 0 threads uses one per core.
+    pub fn rasterize(self, canvas: &mut Framebuffer, depth: &mut DepthBuffer, threads: usize) {
+        assert_eq!(canvas.size(), (self.width, self.height));
+        assert_eq!(depth.size(), (self.width, self.height));
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let next = AtomicUsize::new(0);
+        let screen = Mutex::new((canvas, depth));
+        let worker = || {
+            let mut tile = Tile {
+                origin: Point::new(0, 0),
+                color: Framebuffer::new(TILE_SIZE, TILE_SIZE),
+                depth: DepthBuffer::new(TILE_SIZE, TILE_SIZE),
+            };
+            // Tiles are handed out one at a time, busy ones don't hold up the rest
+            loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let bin = match self.tiles.get(idx) {
+                    Some(bin) => bin,
+                    None => break,
+                };
+                if bin.is_empty() {
+                    continue;
+                }
+                self.load(&mut tile, idx, &screen);
+                for &(batch, triangle) in bin {
+                    self.batches[batch].draw(triangle, &mut tile);
+                }
+                self.store(&tile, &screen);
+            }
+        };
+        match threads.min(self.tiles.len()) {
+            0 | 1 => worker(),
+            threads => thread::scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(worker);
+                }
+            }),
+        }
+    }
+    // Sets the tile up to cover tile `idx`, with what is on screen there so far
+    fn load(
+        &self,
+        tile: &mut Tile,
+        idx: usize,
+        screen: &Mutex<(&mut Framebuffer, &mut DepthBuffer)>,
+    ) {
+        let (column, row) = (idx as u32 % self.columns, idx as u32 / self.columns);
+        let origin = Point::new((column * TILE_SIZE) as i32, (row * TILE_SIZE) as i32);
+        let width = TILE_SIZE.min(self.width - column * TILE_SIZE);
+        let height = TILE_SIZE.min(self.height - row * TILE_SIZE);
+        tile.origin = origin;
+        if tile.color.size() != (width, height) {
+            tile.color.resize(width, height);
+            tile.depth.resize(width, height);
+        }
+        let (canvas, depth) = &mut *screen.lock().unwrap();
+        let (start, size) = (Point::new(0, 0), (width, height));
+        let tile_color = tile.color.pixels_mut();
+        copy_rect(
+            canvas.pixels(),
+            self.width,
+            origin,
+            tile_color,
+            width,
+            start,
+            size,
+        );
+        let tile_depth = tile.depth.values_mut();
+        copy_rect(
+            depth.values(),
+            self.width,
+            origin,
+            tile_depth,
+            width,
+            start,
+            size,
+        );
+    }
+    fn store(&self, tile: &Tile, screen: &Mutex<(&mut Framebuffer, &mut DepthBuffer)>) {
+        let (start, size) = (Point::new(0, 0), tile.color.size());
+        let (canvas, depth) = &mut *screen.lock().unwrap();
+        let (color, depths) = (tile.color.pixels(), tile.depth.values());
+        copy_rect(
+            color,
+            size.0,
+            start,
+            canvas.pixels_mut(),
+            self.width,
+            tile.origin,
+            size,
+        );
+        copy_rect(
+            depths,
+            size.0,
+            start,
+            depth.values_mut(),
+            self.width,
+            tile.origin,
+            size,
+        );
+    }
+}
+
+impl<'a> TriangleSink<'a> for Bins<'a> {
+    fn submit<V, FS>(&mut self, fragment: FS, triangles: Vec<[Xyzv<V>; 3]>)
+    where
+        V: Lerp + Send + Sync + 'a,
+        FS: FragmentShader<V> + Sync + 'a,
+    {
+        let batch = self.batches.len();
+        for (idx, [p0, p1, p2]) in triangles.iter().enumerate() {
+            // Pixels that might have their center inside, a bit more is only slower
+            let min_x = p0.x.min(p1.x).min(p2.x).floor();
+            let min_y = p0.y.min(p1.y).min(p2.y).floor();
+            let max_x = p0.x.max(p1.x).max(p2.x).floor();
+            let max_y = p0.y.max(p1.y).max(p2.y).floor();
+            if max_x < 0.0 || max_y < 0.0 || min_x >= self.width as FP || min_y >= self.height as FP
+            {
+                continue;
+            }
+            let tile = |v: FP, tiles: u32| ((v.max(0.0) as u32) / TILE_SIZE).min(tiles - 1);
+            for row in tile(min_y, self.rows)..=tile(max_y, self.rows) {
+                for column in tile(min_x, self.columns)..=tile(max_x, self.columns) {
+                    self.tiles[(row * self.columns + column) as usize].push((batch, idx));
+                }
+            }
+        }
+        self.batches.push(Box::new(ShadedBatch {
+            fragment,
+            triangles,
+        }));
+    }
+}